@@ -0,0 +1,104 @@
+use crate::vec::Vec3;
+use crate::object::Object;
+use crate::material::SurfacePoint;
+use crate::texture::Texture;
+use serde::{Serialize,Deserialize};
+
+/// Fine surface detail tilting the shading normal, without changing the geometry
+#[derive(Serialize,Deserialize,Debug)]
+pub struct Relief {
+    /// Height field, its slope tilts the normal. Image heights need texture coordinates,
+    /// 3D textures work on every shape.
+    #[serde(default)]
+    bump: Option<Box<dyn Texture>>,
+    /// Height of a bump value of 1, in object units
+    #[serde(default = "Relief::default_bump_strength")]
+    bump_strength: f32,
+    /// Tangent-space normal map, with green toward the top of the image. Images are read
+    /// with the `linear` color space unless they set one.
+    #[serde(default)]
+    normal_map: Option<Box<dyn Texture>>,
+}
+
+impl Default for Relief {
+    fn default() -> Relief {
+        Relief {
+            bump: None,
+            bump_strength: Relief::default_bump_strength(),
+            normal_map: None,
+        }
+    }
+}
+
+impl Relief {
+    pub fn default_bump_strength() -> f32 {
+        0.02
+    }
+
+    pub fn init(&mut self) {
+        if let Some(bump) = self.bump.as_mut() {
+            bump.init();
+        }
+        if let Some(normal_map) = self.normal_map.as_mut() {
+            normal_map.init_data();
+        }
+    }
+
+    pub fn is_flat(&self) -> bool {
+        self.bump.is_none() && self.normal_map.is_none()
+    }
+
+    /// Unit shading normal in world space at the surface point of `object`
+    pub fn normal(&self, surface: &SurfacePoint, object: &Object) -> Vec3 {
+        // Detail is worked out in object space, where the shape reports its derivatives
+        let mut norm = ((&object.rotation * &surface.norm) * &object.size).norm();
+
+        if let Some(bump) = &self.bump {
+            norm = match surface.derivatives.as_ref() {
+                Some((dpdu, dpdv)) => {
+                    // Heights a small step away along u and v, the step matching the texture detail
+                    let step = surface.footprint.clamp(1e-4, 1e-2);
+                    let height = bump.value(surface);
+                    let slope = |dp: &Vec3, du: f32, dv: f32| {
+                        let (u, v) = surface.uv.unwrap_or((0.0, 0.0));
+                        let shifted = surface.shifted(&(dp * step), Some((u + du * step, v + dv * step)));
+                        (bump.value(&shifted) - height) / step * self.bump_strength
+                    };
+                    let displaced_u = dpdu + &norm * slope(dpdu, 1.0, 0.0);
+                    let displaced_v = dpdv + &norm * slope(dpdv, 0.0, 1.0);
+                    let bumped = displaced_u.cross(&displaced_v).norm();
+                    if bumped.dot(&norm) < 0.0 { -bumped } else { bumped }
+                },
+                None => {
+                    let step = 1e-3;
+                    let height = bump.value(surface);
+                    let slope = |axis: Vec3| {
+                        (bump.value(&surface.shifted(&(axis * step), surface.uv)) - height) / step
+                    };
+                    let gradient = Vec3::new(slope(Vec3::new(1.0, 0.0, 0.0)),
+                                             slope(Vec3::new(0.0, 1.0, 0.0)),
+                                             slope(Vec3::new(0.0, 0.0, 1.0)));
+                    // Only the part of the slope along the surface tilts it
+                    let along = &gradient - &norm * gradient.dot(&norm);
+                    (&norm - along * self.bump_strength).norm()
+                },
+            };
+        }
+
+        if let (Some(normal_map), Some((dpdu, dpdv))) = (&self.normal_map, surface.derivatives.as_ref()) {
+            let tangent = (dpdu - &norm * dpdu.dot(&norm)).norm();
+            let mut bitangent = norm.cross(&tangent);
+            // Image rows run along v, so the top of the image is toward decreasing v
+            if bitangent.dot(dpdv) > 0.0 {
+                bitangent = -bitangent;
+            }
+            let [x, y, z] = normal_map.color(surface).map(|c| 2.0 * c - 1.0);
+            let mapped = (tangent * x + bitangent * y + &norm * z).norm();
+            if mapped.dot(&norm) > 0.0 {
+                norm = mapped;
+            }
+        }
+
+        ((norm / &object.size) * &object.rotation).norm()
+    }
+}
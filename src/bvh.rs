@@ -0,0 +1,420 @@
+use crate::vec::Vec3;
+
+/// Axis aligned bounding box
+#[derive(Clone,Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Aabb {
+        Aabb{min, max}
+    }
+
+    /// Box containing nothing, neutral element for `union`
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Vec3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: Vec3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_points<'a>(points: impl IntoIterator<Item = &'a Vec3>) -> Aabb {
+        let mut result = Aabb::empty();
+        for point in points {
+            result.grow(point);
+        }
+        result
+    }
+
+    pub fn grow(&mut self, point: &Vec3) {
+        self.min = self.min.min(point);
+        self.max = self.max.max(point);
+    }
+
+    pub fn union(&self, rhs: &Aabb) -> Aabb {
+        Aabb::new(self.min.min(&rhs.min), self.max.max(&rhs.max))
+    }
+
+    pub fn intersection(&self, rhs: &Aabb) -> Aabb {
+        Aabb::new(self.min.max(&rhs.min), self.max.min(&rhs.max))
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        let (a, b) = (&self.min, &self.max);
+        [Vec3::new(a.x(), a.y(), a.z()), Vec3::new(b.x(), a.y(), a.z()),
+         Vec3::new(a.x(), b.y(), a.z()), Vec3::new(b.x(), b.y(), a.z()),
+         Vec3::new(a.x(), a.y(), b.z()), Vec3::new(b.x(), a.y(), b.z()),
+         Vec3::new(a.x(), b.y(), b.z()), Vec3::new(b.x(), b.y(), b.z())]
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let extent = &self.max - &self.min;
+        2.0 * (extent.x() * extent.y() + extent.y() * extent.z() + extent.z() * extent.x())
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (&self.min + &self.max) * 0.5
+    }
+
+    /// Parametric distance at which the ray enters the box, clipped to `[t_min, t_max]`
+    pub fn hit(&self, start: &Vec3, inv_ray: &Vec3, t_min: f32, t_max: f32) -> Option<f32> {
+        let mut t_near = t_min;
+        let mut t_far = t_max;
+        for axis in 0..3 {
+            let t1 = (self.min[axis] - start[axis]) * inv_ray[axis];
+            let t2 = (self.max[axis] - start[axis]) * inv_ray[axis];
+            t_near = t_near.max(t1.min(t2));
+            t_far = t_far.min(t1.max(t2));
+        }
+        if t_near <= t_far {
+            Some(t_near)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+enum BvhNode {
+    Leaf {
+        bounds: Aabb,
+        first: usize,
+        count: usize,
+    },
+    Node {
+        bounds: Aabb,
+        left: usize,
+        right: usize,
+    },
+}
+
+impl BvhNode {
+    fn bounds(&self) -> &Aabb {
+        match self {
+            BvhNode::Leaf{bounds, ..} => bounds,
+            BvhNode::Node{bounds, ..} => bounds,
+        }
+    }
+}
+
+/// Node of the tree while it is being built, flattened into `BvhNode`s afterwards
+enum BuildNode {
+    Leaf {
+        bounds: Aabb,
+        first: usize,
+        count: usize,
+    },
+    Node {
+        bounds: Aabb,
+        left: Box<BuildNode>,
+        right: Box<BuildNode>,
+    },
+}
+
+#[derive(Clone)]
+struct Bin {
+    bounds: Aabb,
+    count: usize,
+}
+
+/// Bounding volume hierarchy over primitives given by their bounding boxes.
+/// Queries report primitives by their index in the slice the tree was built from.
+#[derive(Debug,Default)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    indices: Vec<usize>,
+}
+
+impl Bvh {
+    pub const DEFAULT_LEAF_SIZE: usize = 4;
+    const BINS: usize = 16;
+    /// Subtrees with fewer primitives are built on the current thread
+    const PARALLEL_THRESHOLD: usize = 4096;
+    /// Cost of a traversal step relative to a primitive intersection test
+    const TRAVERSAL_COST: f32 = 1.0;
+
+    /// Builds the tree with the surface area heuristic, using binned splits.
+    /// Leaves hold at most `leaf_size` primitives unless they can not be split.
+    pub fn new(bounds: &[Aabb], leaf_size: usize) -> Bvh {
+        let mut indices: Vec<usize> = (0..bounds.len()).collect();
+        let mut bvh = Bvh {
+            nodes: vec![],
+            indices: vec![],
+        };
+        if !bounds.is_empty() {
+            let centroids: Vec<Vec3> = bounds.iter().map(|b| b.centroid()).collect();
+            let root = Bvh::build(bounds, &centroids, &mut indices, 0, leaf_size.max(1));
+            bvh.flatten(root);
+        }
+        bvh.indices = indices;
+        bvh
+    }
+
+    /// Bounds of the whole tree
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.nodes.first().map(|node| node.bounds().clone())
+    }
+
+    /// Builds the subtree over `indices`, which start at `first` in the final index list
+    fn build(bounds: &[Aabb], centroids: &[Vec3], indices: &mut [usize], first: usize, leaf_size: usize) -> BuildNode {
+        let count = indices.len();
+        let node_bounds = indices.iter().fold(Aabb::empty(), |acc, &i| acc.union(&bounds[i]));
+        let leaf = |node_bounds| BuildNode::Leaf{bounds: node_bounds, first, count};
+        if count == 1 {
+            return leaf(node_bounds);
+        }
+
+        let centroid_bounds = Aabb::from_points(indices.iter().map(|&i| &centroids[i]));
+        let bin_of = |axis: usize, centroid: &Vec3| -> usize {
+            let extent = centroid_bounds.max[axis] - centroid_bounds.min[axis];
+            let bin = ((centroid[axis] - centroid_bounds.min[axis]) / extent * Bvh::BINS as f32) as usize;
+            bin.min(Bvh::BINS - 1)
+        };
+
+        // (cost, axis, first bin of the right side)
+        let mut best_split: Option<(f32, usize, usize)> = None;
+        for axis in 0..3 {
+            if centroid_bounds.max[axis] - centroid_bounds.min[axis] <= 0.0 {
+                continue;
+            }
+            let mut bins = vec![Bin{bounds: Aabb::empty(), count: 0}; Bvh::BINS];
+            for &i in indices.iter() {
+                let bin = &mut bins[bin_of(axis, &centroids[i])];
+                bin.bounds = bin.bounds.union(&bounds[i]);
+                bin.count += 1;
+            }
+
+            // Sweep from the right to know the cost of every right side, then from the left
+            let mut right_costs = [0.0; Bvh::BINS];
+            let mut right = Bin{bounds: Aabb::empty(), count: 0};
+            for split in (1..Bvh::BINS).rev() {
+                right.bounds = right.bounds.union(&bins[split].bounds);
+                right.count += bins[split].count;
+                right_costs[split] = right.bounds.surface_area() * right.count as f32;
+            }
+            let mut left = Bin{bounds: Aabb::empty(), count: 0};
+            for split in 1..Bvh::BINS {
+                left.bounds = left.bounds.union(&bins[split - 1].bounds);
+                left.count += bins[split - 1].count;
+                if left.count == 0 || left.count == count {
+                    continue;
+                }
+                let cost = left.bounds.surface_area() * left.count as f32 + right_costs[split];
+                if best_split.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                    best_split = Some((cost, axis, split));
+                }
+            }
+        }
+
+        let (cost, axis, split) = match best_split {
+            Some(best_split) => best_split,
+            None => return leaf(node_bounds),
+        };
+        let area = node_bounds.surface_area();
+        let split_cost = Bvh::TRAVERSAL_COST + cost / area;
+        if count <= leaf_size && (count as f32) <= split_cost {
+            return leaf(node_bounds);
+        }
+
+        let mut mid = 0;
+        for j in 0..count {
+            if bin_of(axis, &centroids[indices[j]]) < split {
+                indices.swap(mid, j);
+                mid += 1;
+            }
+        }
+
+        let (left_indices, right_indices) = indices.split_at_mut(mid);
+        let (left, right) = if count >= Bvh::PARALLEL_THRESHOLD {
+            rayon::join(|| Bvh::build(bounds, centroids, left_indices, first, leaf_size),
+                        || Bvh::build(bounds, centroids, right_indices, first + mid, leaf_size))
+        } else {
+            (Bvh::build(bounds, centroids, left_indices, first, leaf_size),
+             Bvh::build(bounds, centroids, right_indices, first + mid, leaf_size))
+        };
+        BuildNode::Node{bounds: node_bounds, left: Box::new(left), right: Box::new(right)}
+    }
+
+    /// Appends the subtree to `nodes`, returns the index of its root
+    fn flatten(&mut self, node: BuildNode) -> usize {
+        match node {
+            BuildNode::Leaf{bounds, first, count} => {
+                self.nodes.push(BvhNode::Leaf{bounds, first, count});
+                self.nodes.len() - 1
+            },
+            BuildNode::Node{bounds, left, right} => {
+                let index = self.nodes.len();
+                self.nodes.push(BvhNode::Leaf{bounds: Aabb::empty(), first: 0, count: 0});
+                let left = self.flatten(*left);
+                let right = self.flatten(*right);
+                self.nodes[index] = BvhNode::Node{bounds, left, right};
+                index
+            }
+        }
+    }
+
+    /// Finds the closest primitive hit within `[t_min, t_max]`.
+    /// `hit` tests a single primitive and returns its distance with a payload.
+    pub fn closest_hit<T, F>(&self, start: &Vec3, ray: &Vec3, t_min: f32, t_max: f32, mut hit: F) -> Option<(f32, T)>
+        where F: FnMut(usize) -> Option<(f32, T)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let inv_ray = Vec3::new(1.0 / ray.x(), 1.0 / ray.y(), 1.0 / ray.z());
+        let mut best: Option<(f32, T)> = None;
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let t_limit = best.as_ref().map_or(t_max, |b| b.0);
+            if self.nodes[node].bounds().hit(start, &inv_ray, t_min, t_limit).is_none() {
+                continue;
+            }
+            match &self.nodes[node] {
+                BvhNode::Leaf{first, count, ..} => {
+                    for &index in &self.indices[*first..*first + *count] {
+                        if let Some((distance, payload)) = hit(index) {
+                            if distance >= t_min && distance <= best.as_ref().map_or(t_max, |b| b.0) {
+                                best = Some((distance, payload));
+                            }
+                        }
+                    }
+                },
+                BvhNode::Node{left, right, ..} => {
+                    let left_t = self.nodes[*left].bounds().hit(start, &inv_ray, t_min, t_limit);
+                    let right_t = self.nodes[*right].bounds().hit(start, &inv_ray, t_min, t_limit);
+                    // Visit the nearer child first, so it is pushed last
+                    match (left_t, right_t) {
+                        (Some(l), Some(r)) => if l < r {
+                            stack.push(*right);
+                            stack.push(*left);
+                        } else {
+                            stack.push(*left);
+                            stack.push(*right);
+                        },
+                        (Some(_), None) => stack.push(*left),
+                        (None, Some(_)) => stack.push(*right),
+                        (None, None) => {},
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    /// Returns true as soon as `hit` accepts any primitive whose box the ray crosses within `[t_min, t_max]`
+    pub fn any_hit<F>(&self, start: &Vec3, ray: &Vec3, t_min: f32, t_max: f32, mut hit: F) -> bool
+        where F: FnMut(usize) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+        let inv_ray = Vec3::new(1.0 / ray.x(), 1.0 / ray.y(), 1.0 / ray.z());
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            if self.nodes[node].bounds().hit(start, &inv_ray, t_min, t_max).is_none() {
+                continue;
+            }
+            match &self.nodes[node] {
+                BvhNode::Leaf{first, count, ..} => {
+                    if self.indices[*first..*first + *count].iter().any(|&index| hit(index)) {
+                        return true;
+                    }
+                },
+                BvhNode::Node{left, right, ..} => {
+                    stack.push(*left);
+                    stack.push(*right);
+                }
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng,SeedableRng};
+    use rand::rngs::SmallRng;
+
+    fn random_point(rng: &mut SmallRng, extent: f32) -> Vec3 {
+        Vec3::new(rng.gen_range(-extent..extent), rng.gen_range(-extent..extent), rng.gen_range(-extent..extent))
+    }
+
+    /// Random small boxes, enough for the top of the tree to be built in parallel
+    fn random_boxes(rng: &mut SmallRng) -> Vec<Aabb> {
+        (0..Bvh::PARALLEL_THRESHOLD + 1000).map(|_| {
+            let min = random_point(rng, 10.0);
+            let max = &min + Vec3::new(rng.gen_range(0.0..0.5), rng.gen_range(0.0..0.5), rng.gen_range(0.0..0.5));
+            Aabb::new(min, max)
+        }).collect()
+    }
+
+    /// Rays from random points in random directions, with open and bounded ranges
+    fn random_rays(rng: &mut SmallRng) -> Vec<(Vec3, Vec3, f32, f32)> {
+        (0..500).map(|i| {
+            let start = random_point(rng, 15.0);
+            let direction = random_point(rng, 1.0).norm();
+            let (t_min, t_max) = match i % 3 {
+                0 => (0.0, f32::INFINITY),
+                1 => (rng.gen_range(0.0..10.0), f32::INFINITY),
+                _ => {
+                    let t_min = rng.gen_range(0.0..10.0);
+                    (t_min, t_min + rng.gen_range(0.0..10.0))
+                },
+            };
+            (start, direction, t_min, t_max)
+        }).collect()
+    }
+
+    fn inverse(ray: &Vec3) -> Vec3 {
+        Vec3::new(1.0 / ray.x(), 1.0 / ray.y(), 1.0 / ray.z())
+    }
+
+    /// Distance at which the ray enters the box, the boxes stand for primitives
+    fn box_hit(bounds: &Aabb, start: &Vec3, ray: &Vec3) -> Option<f32> {
+        bounds.hit(start, &inverse(ray), f32::NEG_INFINITY, f32::INFINITY)
+    }
+
+    #[test]
+    fn closest_hit_matches_brute_force() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        let boxes = random_boxes(&mut rng);
+        for leaf_size in [1, Bvh::DEFAULT_LEAF_SIZE, 16] {
+            let bvh = Bvh::new(&boxes, leaf_size);
+            for (start, ray, t_min, t_max) in random_rays(&mut rng) {
+                let expected = boxes.iter()
+                    .filter_map(|bounds| box_hit(bounds, &start, &ray))
+                    .filter(|&t| t >= t_min && t <= t_max)
+                    .min_by(f32::total_cmp);
+                let found = bvh.closest_hit(&start, &ray, t_min, t_max, |index| {
+                    box_hit(&boxes[index], &start, &ray).map(|t| (t, index))
+                });
+                assert_eq!(found.map(|(t, _)| t), expected);
+                if let Some((t, index)) = found {
+                    assert_eq!(box_hit(&boxes[index], &start, &ray), Some(t));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn any_hit_matches_brute_force() {
+        let mut rng = SmallRng::seed_from_u64(2);
+        let boxes = random_boxes(&mut rng);
+        let bvh = Bvh::new(&boxes, Bvh::DEFAULT_LEAF_SIZE);
+        for (start, ray, t_min, t_max) in random_rays(&mut rng) {
+            let blocks = |index: usize| box_hit(&boxes[index], &start, &ray).is_some_and(|t| t >= t_min && t <= t_max);
+            let expected = (0..boxes.len()).any(blocks);
+            assert_eq!(bvh.any_hit(&start, &ray, t_min, t_max, blocks), expected);
+        }
+    }
+
+    #[test]
+    fn empty_tree_hits_nothing() {
+        let bvh = Bvh::new(&[], Bvh::DEFAULT_LEAF_SIZE);
+        let (start, ray) = (Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(bvh.bounds().is_none());
+        assert!(bvh.closest_hit(&start, &ray, 0.0, f32::INFINITY, |index| Some((0.0, index))).is_none());
+        assert!(!bvh.any_hit(&start, &ray, 0.0, f32::INFINITY, |_| true));
+    }
+}
@@ -0,0 +1,341 @@
+use crate::vec::Vec3;
+use crate::matrix::Matrix33;
+use crate::scene::Scene;
+use crate::sampling::concentric_disk;
+use serde::{Serialize,Deserialize};
+
+use std::f32::consts::PI;
+
+#[typetag::serde(tag="type")]
+pub trait Camera: Sync + Send {
+    /// Prepares the camera for rendering an image of `img_size` pixels of the initialized scene
+    fn init(&mut self, img_size: (i32, i32), objects: &Scene);
+    /// Start and normalized direction of the primary ray through the image point `(x, y)`,
+    /// in pixels from the top left corner of the image, `None` outside of what the projection covers.
+    /// `lens` is a uniform sample of the unit square, used by cameras with an aperture.
+    fn ray(&self, x: f32, y: f32, lens: (f32, f32)) -> Option<(Vec3, Vec3)>;
+    /// Angle in radians between the rays through neighbouring pixels,
+    /// 0 when rays don't spread apart with distance
+    fn pixel_spread(&self) -> f32 {
+        0.0
+    }
+}
+
+/// Placement and orientation shared by all cameras
+#[derive(Serialize,Deserialize)]
+pub struct CameraFrame {
+    #[serde(default = "Vec3::new_default")]
+    position: Vec3,
+    #[serde(default = "CameraFrame::default_look_at")]
+    look_at: Vec3,
+    /// World -y is up by default, matching the rest of the scene conventions
+    #[serde(default = "CameraFrame::default_up")]
+    up: Vec3,
+    /// Nothing closer to the camera than this is visible
+    #[serde(default)]
+    near: f32,
+
+    #[serde(skip_serializing,skip_deserializing)]
+    forward: Vec3,
+    #[serde(skip_serializing,skip_deserializing)]
+    right: Vec3,
+    #[serde(skip_serializing,skip_deserializing)]
+    screen_up: Vec3,
+    #[serde(skip_serializing,skip_deserializing)]
+    img_size: (f32, f32),
+}
+
+impl CameraFrame {
+    pub fn new(position: Vec3, look_at: Vec3, up: Vec3) -> CameraFrame {
+        CameraFrame {
+            position,
+            look_at,
+            up,
+            near: 0.0,
+            forward: Vec3::default(),
+            right: Vec3::default(),
+            screen_up: Vec3::default(),
+            img_size: (0.0, 0.0),
+        }
+    }
+
+    fn init(&mut self, img_size: (i32, i32)) {
+        self.forward = (&self.look_at - &self.position).norm();
+        self.right = self.forward.cross(&self.up).norm();
+        self.screen_up = self.right.cross(&self.forward);
+        self.img_size = (img_size.0 as f32, img_size.1 as f32);
+    }
+
+    fn aspect(&self) -> f32 {
+        self.img_size.0 / self.img_size.1
+    }
+
+    /// Image point mapped to [-1, 1] on both axes, y pointing up
+    fn screen(&self, x: f32, y: f32) -> (f32, f32) {
+        (2.0 * x / self.img_size.0 - 1.0, 1.0 - 2.0 * y / self.img_size.1)
+    }
+
+    /// Converts (right, up, forward) camera coordinates to world orientation
+    fn to_world(&self, x: f32, y: f32, z: f32) -> Vec3 {
+        &self.right * x + &self.screen_up * y + &self.forward * z
+    }
+
+    /// Ray from `start` along `direction`, moved past the near distance
+    fn ray(&self, start: &Vec3, direction: Vec3) -> Option<(Vec3, Vec3)> {
+        let direction = direction.norm();
+        Some((start + &direction * self.near, direction))
+    }
+
+    pub fn default_look_at() -> Vec3 {
+        Vec3::new(0.0, 0.0, 1.0)
+    }
+
+    pub fn default_up() -> Vec3 {
+        Vec3::new(0.0, -1.0, 0.0)
+    }
+}
+
+#[derive(Serialize,Deserialize)]
+pub struct PerspectiveCamera {
+    #[serde(flatten)]
+    frame: CameraFrame,
+    /// Vertical field of view in degrees
+    #[serde(default = "PerspectiveCamera::default_fov")]
+    fov: f32,
+
+    #[serde(skip_serializing,skip_deserializing)]
+    half_size: (f32, f32),
+}
+
+#[typetag::serde(name="perspective")]
+impl Camera for PerspectiveCamera {
+    fn init(&mut self, img_size: (i32, i32), _objects: &Scene) {
+        self.frame.init(img_size);
+        let half_height = (self.fov.to_radians() / 2.0).tan();
+        self.half_size = (half_height * self.frame.aspect(), half_height);
+    }
+
+    fn ray(&self, x: f32, y: f32, _lens: (f32, f32)) -> Option<(Vec3, Vec3)> {
+        self.frame.ray(&self.frame.position, self.direction(x, y))
+    }
+
+    fn pixel_spread(&self) -> f32 {
+        2.0 * self.half_size.1 / self.frame.img_size.1
+    }
+}
+
+impl PerspectiveCamera {
+    pub fn new(position: Vec3, look_at: Vec3, up: Vec3, fov: f32) -> PerspectiveCamera {
+        PerspectiveCamera {
+            frame: CameraFrame::new(position, look_at, up),
+            fov,
+            half_size: (0.0, 0.0),
+        }
+    }
+
+    /// Direction of the pinhole ray through the image point `(x, y)`
+    fn direction(&self, x: f32, y: f32) -> Vec3 {
+        let (screen_x, screen_y) = self.frame.screen(x, y);
+        self.frame.to_world(screen_x * self.half_size.0, screen_y * self.half_size.1, 1.0).norm()
+    }
+
+    /// Camera at `start` rotated by `view_angle` (radians), as configured before camera blocks existed
+    pub fn from_view(start: &Vec3, view_angle: &Vec3) -> PerspectiveCamera {
+        let (x_phi, y_phi, z_phi) = (view_angle.x(), view_angle.y(), view_angle.z());
+        let x_matrix = Matrix33::new([1.0, 0.0, 0.0,
+                                      0.0, f32::cos(x_phi), -f32::sin(x_phi),
+                                      0.0, f32::sin(x_phi), f32::cos(x_phi)]);
+        let y_matrix = Matrix33::new([f32::cos(y_phi), 0.0, f32::sin(y_phi),
+                                      0.0, 1.0, 0.0,
+                                      -f32::sin(y_phi), 0.0, f32::cos(y_phi)]);
+        let z_matrix = Matrix33::new([f32::cos(z_phi), -f32::sin(z_phi), 0.0,
+                                      f32::sin(z_phi), f32::cos(z_phi), 0.0,
+                                      0.0, 0.0, 1.0]);
+        let rotation = x_matrix * y_matrix * z_matrix;
+        let forward = Vec3::new(0.0, 0.0, 1.0) * &rotation;
+        let up = CameraFrame::default_up() * &rotation;
+
+        let mut camera = PerspectiveCamera::new(start.clone(), start + forward, up, PerspectiveCamera::default_fov());
+        // The view used to skip everything closer than the image plane
+        camera.frame.near = 1.0;
+        camera
+    }
+
+    /// Matches the unit image plane at unit distance of the old fixed view
+    pub fn default_fov() -> f32 {
+        2.0 * f32::atan(0.5).to_degrees()
+    }
+}
+
+/// Perspective camera with a finite aperture: only the plane at `focus_distance` is sharp
+#[derive(Serialize,Deserialize)]
+pub struct ThinLensCamera {
+    #[serde(flatten)]
+    perspective: PerspectiveCamera,
+    /// Lens diameter, 0 gives a pinhole camera
+    #[serde(default)]
+    aperture: f32,
+    /// Distance along the view direction to the plane in focus
+    #[serde(default = "ThinLensCamera::default_focus_distance")]
+    focus_distance: f32,
+    /// Name of an object to focus on instead of `focus_distance`
+    #[serde(default)]
+    focus_object: Option<String>,
+}
+
+#[typetag::serde(name="thin_lens")]
+impl Camera for ThinLensCamera {
+    fn init(&mut self, img_size: (i32, i32), objects: &Scene) {
+        self.perspective.init(img_size, objects);
+
+        if let Some(name) = &self.focus_object {
+            let object = objects.find(name).unwrap_or_else(|| panic!("Unknown focus object {}", name));
+            let target = match object.bounds() {
+                Some(bounds) => bounds.centroid(),
+                None => object.position.clone(),
+            };
+            // Focus on the surface facing the camera, or the object center if the ray misses it
+            let frame = &self.perspective.frame;
+            let to_target = &target - &frame.position;
+            let distance = match objects.intersect(&frame.position, &to_target, Some(0.0), None) {
+                Some((intersection, _)) => intersection.distance,
+                None => 1.0,
+            };
+            self.focus_distance = (to_target * distance).dot(&frame.forward);
+        }
+    }
+
+    fn ray(&self, x: f32, y: f32, lens: (f32, f32)) -> Option<(Vec3, Vec3)> {
+        let frame = &self.perspective.frame;
+        let direction = self.perspective.direction(x, y);
+        if self.aperture <= 0.0 {
+            return frame.ray(&frame.position, direction);
+        }
+
+        let focus_point = &frame.position + &direction * (self.focus_distance / direction.dot(&frame.forward));
+        let (lens_x, lens_y) = concentric_disk(lens.0, lens.1);
+        let radius = self.aperture / 2.0;
+        let lens_point = &frame.position + frame.to_world(lens_x * radius, lens_y * radius, 0.0);
+
+        frame.ray(&lens_point, focus_point - &lens_point)
+    }
+
+    fn pixel_spread(&self) -> f32 {
+        self.perspective.pixel_spread()
+    }
+}
+
+impl ThinLensCamera {
+    pub fn default_focus_distance() -> f32 {
+        1.0
+    }
+}
+
+/// Parallel rays through a view rectangle `height` world units tall
+#[derive(Serialize,Deserialize)]
+pub struct OrthographicCamera {
+    #[serde(flatten)]
+    frame: CameraFrame,
+    #[serde(default = "OrthographicCamera::default_height")]
+    height: f32,
+}
+
+#[typetag::serde(name="orthographic")]
+impl Camera for OrthographicCamera {
+    fn init(&mut self, img_size: (i32, i32), _objects: &Scene) {
+        self.frame.init(img_size);
+    }
+
+    fn ray(&self, x: f32, y: f32, _lens: (f32, f32)) -> Option<(Vec3, Vec3)> {
+        let (screen_x, screen_y) = self.frame.screen(x, y);
+        let half_height = self.height / 2.0;
+        let offset = self.frame.to_world(screen_x * half_height * self.frame.aspect(), screen_y * half_height, 0.0);
+        self.frame.ray(&(&self.frame.position + offset), self.frame.forward.clone())
+    }
+}
+
+impl OrthographicCamera {
+    pub fn default_height() -> f32 {
+        2.0
+    }
+}
+
+/// Equidistant fisheye: the angle to the view direction grows linearly with the distance
+/// from the image center. The image circle fits the shorter side, outside of it stays black.
+#[derive(Serialize,Deserialize)]
+pub struct FisheyeCamera {
+    #[serde(flatten)]
+    frame: CameraFrame,
+    /// Angle across the image circle in degrees
+    #[serde(default = "FisheyeCamera::default_fov")]
+    fov: f32,
+}
+
+#[typetag::serde(name="fisheye")]
+impl Camera for FisheyeCamera {
+    fn init(&mut self, img_size: (i32, i32), _objects: &Scene) {
+        self.frame.init(img_size);
+    }
+
+    fn ray(&self, x: f32, y: f32, _lens: (f32, f32)) -> Option<(Vec3, Vec3)> {
+        let (screen_x, screen_y) = self.frame.screen(x, y);
+        let aspect = self.frame.aspect();
+        let (circle_x, circle_y) = if aspect > 1.0 {
+            (screen_x * aspect, screen_y)
+        } else {
+            (screen_x, screen_y / aspect)
+        };
+        let r = (circle_x * circle_x + circle_y * circle_y).sqrt();
+        if r > 1.0 {
+            return None;
+        }
+
+        let theta = r * self.fov.to_radians() / 2.0;
+        let (cos_phi, sin_phi) = if r > 0.0 {
+            (circle_x / r, circle_y / r)
+        } else {
+            (0.0, 0.0)
+        };
+        let direction = self.frame.to_world(theta.sin() * cos_phi, theta.sin() * sin_phi, theta.cos());
+        self.frame.ray(&self.frame.position, direction)
+    }
+
+    fn pixel_spread(&self) -> f32 {
+        let (width, height) = self.frame.img_size;
+        self.fov.to_radians() / width.min(height)
+    }
+}
+
+impl FisheyeCamera {
+    pub fn default_fov() -> f32 {
+        180.0
+    }
+}
+
+/// Latitude/longitude panorama of everything around the camera, `look_at` is in the image center
+#[derive(Serialize,Deserialize)]
+pub struct EquirectangularCamera {
+    #[serde(flatten)]
+    frame: CameraFrame,
+}
+
+#[typetag::serde(name="equirectangular")]
+impl Camera for EquirectangularCamera {
+    fn init(&mut self, img_size: (i32, i32), _objects: &Scene) {
+        self.frame.init(img_size);
+    }
+
+    fn ray(&self, x: f32, y: f32, _lens: (f32, f32)) -> Option<(Vec3, Vec3)> {
+        let (screen_x, screen_y) = self.frame.screen(x, y);
+        let longitude = screen_x * PI;
+        let latitude = screen_y * PI / 2.0;
+        let direction = self.frame.to_world(latitude.cos() * longitude.sin(),
+                                             latitude.sin(),
+                                             latitude.cos() * longitude.cos());
+        self.frame.ray(&self.frame.position, direction)
+    }
+
+    fn pixel_spread(&self) -> f32 {
+        PI / self.frame.img_size.1
+    }
+}
@@ -0,0 +1,335 @@
+use crate::vec::Vec3;
+use crate::sampling::{Distribution,uniform_sphere};
+use crate::lights::{Light,DirectionalLight};
+use serde::{Serialize,Deserialize};
+use crate::texture::load_image;
+use image::Rgb32FImage;
+
+use std::f32::consts::PI;
+
+/// Surroundings of the scene, seen by rays that miss every object and lighting the scene
+#[typetag::serde(tag="type")]
+pub trait Environment: Sync + Send {
+    fn init(&mut self) {
+        // empty
+    }
+    /// Radiance arriving from the unit `direction`
+    fn radiance(&self, direction: &Vec3) -> [f32; 3];
+    /// Direction toward the environment for `u` and `v` uniform in [0, 1),
+    /// with its probability density per solid angle
+    fn sample(&self, u: f32, v: f32) -> (Vec3, f32) {
+        (uniform_sphere(u, v), 1.0 / (4.0 * PI))
+    }
+    /// Shadow rays toward the environment per shaded point
+    fn samples(&self) -> u32;
+    /// Lights that are part of the environment, such as the sun of a sky
+    fn lights(&self) -> Vec<Box<dyn Light>> {
+        vec![]
+    }
+}
+
+/// Up direction of environments, world -y as elsewhere in the scene
+fn up() -> Vec3 {
+    Vec3::new(0.0, -1.0, 0.0)
+}
+
+fn lerp(a: &[f32; 3], b: &[f32; 3], t: f32) -> [f32; 3] {
+    [0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * t)
+}
+
+/// Same radiance from every direction
+#[derive(Serialize,Deserialize)]
+pub struct ColorEnvironment {
+    /// Linear RGB
+    color: [f32; 3],
+    #[serde(default = "ColorEnvironment::default_samples")]
+    samples: u32,
+}
+
+#[typetag::serde(name="color")]
+impl Environment for ColorEnvironment {
+    fn radiance(&self, _direction: &Vec3) -> [f32; 3] {
+        self.color
+    }
+
+    fn samples(&self) -> u32 {
+        self.samples
+    }
+}
+
+impl ColorEnvironment {
+    pub fn default_samples() -> u32 {
+        16
+    }
+}
+
+/// Sky fading from `horizon` to `zenith` above, and from `horizon` to `ground` below
+#[derive(Serialize,Deserialize)]
+pub struct GradientEnvironment {
+    zenith: [f32; 3],
+    horizon: [f32; 3],
+    /// Defaults to the horizon color
+    #[serde(default)]
+    ground: Option<[f32; 3]>,
+    #[serde(default = "GradientEnvironment::default_samples")]
+    samples: u32,
+}
+
+#[typetag::serde(name="gradient")]
+impl Environment for GradientEnvironment {
+    fn radiance(&self, direction: &Vec3) -> [f32; 3] {
+        let height = direction.dot(&up());
+        if height >= 0.0 {
+            lerp(&self.horizon, &self.zenith, height)
+        } else {
+            lerp(&self.horizon, self.ground.as_ref().unwrap_or(&self.horizon), -height)
+        }
+    }
+
+    fn samples(&self) -> u32 {
+        self.samples
+    }
+}
+
+impl GradientEnvironment {
+    pub fn default_samples() -> u32 {
+        16
+    }
+}
+
+/// Latitude/longitude panorama, usually an HDR (`.hdr`, `.exr`) photo of real surroundings.
+/// Bright parts such as the sun get most of the shadow rays.
+#[derive(Serialize,Deserialize)]
+pub struct ImageEnvironment {
+    path: String,
+    /// Multiplier of the image values
+    #[serde(default = "ImageEnvironment::default_intensity")]
+    intensity: f32,
+    /// Turns the panorama around the up axis, in degrees
+    #[serde(default)]
+    rotation: f32,
+    #[serde(default = "ImageEnvironment::default_samples")]
+    samples: u32,
+
+    #[serde(skip_serializing,skip_deserializing)]
+    image: Rgb32FImage,
+    /// Rows weighted by their brightness
+    #[serde(skip_serializing,skip_deserializing)]
+    rows: Distribution,
+    /// Pixels of every row weighted by their brightness
+    #[serde(skip_serializing,skip_deserializing)]
+    columns: Vec<Distribution>,
+}
+
+#[typetag::serde(name="image")]
+impl Environment for ImageEnvironment {
+    fn init(&mut self) {
+        self.image = load_image(&self.path).unwrap_or_else(|_| panic!("Failed to load image {}", self.path));
+
+        let (width, height) = self.image.dimensions();
+        let mut row_weights = Vec::with_capacity(height as usize);
+        self.columns = (0..height).map(|y| {
+            // Rows near the poles cover a smaller part of the sphere
+            let sin_theta = (PI * (y as f32 + 0.5) / height as f32).sin();
+            let weights: Vec<f32> = (0..width).map(|x| {
+                let pixel = self.image.get_pixel(x, y);
+                (0.2126 * pixel[0] + 0.7152 * pixel[1] + 0.0722 * pixel[2]) * sin_theta
+            }).collect();
+            let columns = Distribution::new(&weights);
+            row_weights.push(columns.total());
+            columns
+        }).collect();
+        self.rows = Distribution::new(&row_weights);
+    }
+
+    fn radiance(&self, direction: &Vec3) -> [f32; 3] {
+        let (u, v) = self.image_point(direction);
+        let (width, height) = self.image.dimensions();
+        let x = ((u * width as f32) as u32).min(width - 1);
+        let y = ((v * height as f32) as u32).min(height - 1);
+        self.image.get_pixel(x, y).0.map(|c| c * self.intensity)
+    }
+
+    fn sample(&self, u: f32, v: f32) -> (Vec3, f32) {
+        let Some((y, v_offset)) = self.rows.sample(u) else {
+            return (uniform_sphere(u, v), 1.0 / (4.0 * PI));
+        };
+        let (x, u_offset) = self.columns[y].sample(v).expect("Rows with weight have pixels with weight");
+        let (width, height) = self.image.dimensions();
+
+        let image_u = (x as f32 + u_offset) / width as f32;
+        let image_v = (y as f32 + v_offset) / height as f32;
+        let direction = self.direction(image_u, image_v);
+
+        // Density over the image, changed to solid angle
+        let density = self.rows.probability(y) * self.columns[y].probability(x) * (width * height) as f32;
+        let sin_theta = (PI * image_v).sin().max(1e-6);
+        (direction, density / (2.0 * PI * PI * sin_theta))
+    }
+
+    fn samples(&self) -> u32 {
+        self.samples
+    }
+}
+
+impl ImageEnvironment {
+    pub fn default_intensity() -> f32 {
+        1.0
+    }
+
+    pub fn default_samples() -> u32 {
+        16
+    }
+
+    /// Image coordinates in [0, 1] of `direction`. The zenith is at the top and +z in the center,
+    /// like in panoramas of the `equirectangular` camera.
+    fn image_point(&self, direction: &Vec3) -> (f32, f32) {
+        let theta = direction.dot(&up()).clamp(-1.0, 1.0).acos();
+        let longitude = direction.x().atan2(direction.z()) - self.rotation.to_radians();
+        ((longitude / (2.0 * PI) + 0.5).rem_euclid(1.0), theta / PI)
+    }
+
+    /// Direction of the image point `(u, v)`, inverse of `image_point`
+    fn direction(&self, u: f32, v: f32) -> Vec3 {
+        let theta = v * PI;
+        let longitude = (u - 0.5) * 2.0 * PI + self.rotation.to_radians();
+        Vec3::new(theta.sin() * longitude.sin(), -theta.cos(), theta.sin() * longitude.cos())
+    }
+}
+
+/// Perez distribution of sky brightness, `theta` from the zenith and `gamma` from the sun
+fn perez(coefficients: &[f32; 5], cos_theta: f32, gamma: f32) -> f32 {
+    let [a, b, c, d, e] = *coefficients;
+    (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+}
+
+/// Clear daylight sky of Preetham, Shirley and Smits, with its sun as a directional light
+#[derive(Serialize,Deserialize)]
+pub struct SkyEnvironment {
+    /// Angle of the sun above the horizon in degrees
+    #[serde(default = "SkyEnvironment::default_sun_elevation")]
+    sun_elevation: f32,
+    /// Angle of the sun around the up axis in degrees, 0 is toward +z and 90 toward +x
+    #[serde(default)]
+    sun_azimuth: f32,
+    /// Haze of the air, from 2 for a very clear day to about 10
+    #[serde(default = "SkyEnvironment::default_turbidity")]
+    turbidity: f32,
+    /// Scale of the sky luminance, which the model gives in kcd/m²
+    #[serde(default = "SkyEnvironment::default_intensity")]
+    intensity: f32,
+    /// Irradiance of the sun before the atmosphere tints and dims it, 0 leaves it out
+    #[serde(default = "SkyEnvironment::default_sun_intensity")]
+    sun_intensity: f32,
+    /// Radiance from below the horizon
+    #[serde(default)]
+    ground: [f32; 3],
+    #[serde(default = "SkyEnvironment::default_samples")]
+    samples: u32,
+
+    /// Perez coefficients and zenith values of luminance Y and chromaticity x, y
+    #[serde(skip_serializing,skip_deserializing)]
+    coefficients: [[f32; 5]; 3],
+    #[serde(skip_serializing,skip_deserializing)]
+    zenith: [f32; 3],
+}
+
+#[typetag::serde(name="sky")]
+impl Environment for SkyEnvironment {
+    fn init(&mut self) {
+        let t = self.turbidity;
+        self.coefficients = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+
+        let theta_sun = PI / 2.0 - self.sun_elevation.to_radians();
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let powers = [theta_sun.powi(3), theta_sun.powi(2), theta_sun, 1.0];
+        let chromaticity = |c2: [f32; 4], c1: [f32; 4], c0: [f32; 4]| -> f32 {
+            (0..4).map(|i| (t * t * c2[i] + t * c1[i] + c0[i]) * powers[i]).sum()
+        };
+        let x = chromaticity([0.00166, -0.00375, 0.00209, 0.0],
+                             [-0.02903, 0.06377, -0.03202, 0.00394],
+                             [0.11693, -0.21196, 0.06052, 0.25886]);
+        let y = chromaticity([0.00275, -0.00610, 0.00317, 0.0],
+                             [-0.04214, 0.08970, -0.04153, 0.00516],
+                             [0.15346, -0.26756, 0.06670, 0.26688]);
+
+        // Perez is relative, scale it to the zenith values
+        let zenith = [luminance, x, y];
+        self.zenith = [0, 1, 2].map(|i| zenith[i] / perez(&self.coefficients[i], 1.0, theta_sun));
+    }
+
+    fn radiance(&self, direction: &Vec3) -> [f32; 3] {
+        let cos_theta = direction.dot(&up());
+        if cos_theta <= 0.0 {
+            return self.ground;
+        }
+        let gamma = direction.dot(&self.sun_direction()).clamp(-1.0, 1.0).acos();
+        let [luminance, x, y] = [0, 1, 2].map(|i| self.zenith[i] * perez(&self.coefficients[i], cos_theta, gamma));
+
+        let luminance = luminance * self.intensity;
+        let (big_x, big_z) = (x / y * luminance, (1.0 - x - y) / y * luminance);
+        [
+            3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z,
+            -0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z,
+            0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z,
+        ].map(|c| c.max(0.0))
+    }
+
+    fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    fn lights(&self) -> Vec<Box<dyn Light>> {
+        if self.sun_intensity <= 0.0 || self.sun_elevation <= 0.0 {
+            return vec![];
+        }
+        vec![Box::new(DirectionalLight::new(-self.sun_direction(), self.sun_intensity, self.sun_color()))]
+    }
+}
+
+impl SkyEnvironment {
+    pub fn default_sun_elevation() -> f32 {
+        45.0
+    }
+
+    pub fn default_turbidity() -> f32 {
+        3.0
+    }
+
+    pub fn default_intensity() -> f32 {
+        0.05
+    }
+
+    pub fn default_sun_intensity() -> f32 {
+        1.0
+    }
+
+    pub fn default_samples() -> u32 {
+        16
+    }
+
+    /// Unit direction toward the sun
+    fn sun_direction(&self) -> Vec3 {
+        let (elevation, azimuth) = (self.sun_elevation.to_radians(), self.sun_azimuth.to_radians());
+        Vec3::new(elevation.cos() * azimuth.sin(), -elevation.sin(), elevation.cos() * azimuth.cos())
+    }
+
+    /// Transmittance of the atmosphere toward the sun for red, green and blue,
+    /// from Rayleigh scattering and the turbidity's aerosols
+    fn sun_color(&self) -> [f32; 3] {
+        let zenith_angle = 90.0 - self.sun_elevation;
+        let air_mass = 1.0 / (zenith_angle.to_radians().cos() + 0.15 * (93.885 - zenith_angle).powf(-1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        // Wavelengths in micrometers
+        [0.680f32, 0.550, 0.440].map(|lambda| {
+            let rayleigh = 0.008735 * lambda.powf(-4.08);
+            let aerosol = beta * lambda.powf(-1.3);
+            (-air_mass * (rayleigh + aerosol)).exp()
+        })
+    }
+}
@@ -0,0 +1,139 @@
+use serde::{Serialize,Deserialize};
+
+/// Pixel reconstruction filter, offsets are in pixels from the pixel center
+#[typetag::serde(tag="type")]
+pub trait Filter: Sync + Send {
+    /// Half-width of the square the filter is non-zero on
+    fn radius(&self) -> f32;
+    fn evaluate(&self, x: f32, y: f32) -> f32;
+}
+
+#[derive(Serialize,Deserialize)]
+pub struct BoxFilter {
+    #[serde(default = "BoxFilter::default_radius")]
+    radius: f32,
+}
+
+#[typetag::serde(name="box")]
+impl Filter for BoxFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, _x: f32, _y: f32) -> f32 {
+        1.0
+    }
+}
+
+impl BoxFilter {
+    pub fn new(radius: f32) -> BoxFilter {
+        BoxFilter{radius}
+    }
+
+    pub fn default_radius() -> f32 {
+        0.5
+    }
+}
+
+#[derive(Serialize,Deserialize)]
+pub struct TentFilter {
+    #[serde(default = "TentFilter::default_radius")]
+    radius: f32,
+}
+
+#[typetag::serde(name="tent")]
+impl Filter for TentFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f32, y: f32) -> f32 {
+        f32::max(0.0, self.radius - x.abs()) * f32::max(0.0, self.radius - y.abs())
+    }
+}
+
+impl TentFilter {
+    pub fn default_radius() -> f32 {
+        1.0
+    }
+}
+
+#[derive(Serialize,Deserialize)]
+pub struct GaussianFilter {
+    #[serde(default = "GaussianFilter::default_radius")]
+    radius: f32,
+    /// Falloff rate, larger values give a sharper image
+    #[serde(default = "GaussianFilter::default_alpha")]
+    alpha: f32,
+}
+
+#[typetag::serde(name="gaussian")]
+impl Filter for GaussianFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f32, y: f32) -> f32 {
+        // Shifted down so the filter reaches zero at its radius
+        let edge = (-self.alpha * self.radius * self.radius).exp();
+        let gaussian = |d: f32| f32::max(0.0, (-self.alpha * d * d).exp() - edge);
+        gaussian(x) * gaussian(y)
+    }
+}
+
+impl GaussianFilter {
+    pub fn default_radius() -> f32 {
+        1.5
+    }
+
+    pub fn default_alpha() -> f32 {
+        2.0
+    }
+}
+
+/// Mitchell–Netravali cubic, `b` and `c` trade blurring against ringing
+#[derive(Serialize,Deserialize)]
+pub struct MitchellFilter {
+    #[serde(default = "MitchellFilter::default_radius")]
+    radius: f32,
+    #[serde(default = "MitchellFilter::default_bc")]
+    b: f32,
+    #[serde(default = "MitchellFilter::default_bc")]
+    c: f32,
+}
+
+#[typetag::serde(name="mitchell")]
+impl Filter for MitchellFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f32, y: f32) -> f32 {
+        self.mitchell_1d(x / self.radius) * self.mitchell_1d(y / self.radius)
+    }
+}
+
+impl MitchellFilter {
+    pub fn default_radius() -> f32 {
+        2.0
+    }
+
+    pub fn default_bc() -> f32 {
+        1.0 / 3.0
+    }
+
+    /// Cubic over [-2, 2], `x` is in [-1, 1]
+    fn mitchell_1d(&self, x: f32) -> f32 {
+        let (b, c) = (self.b, self.c);
+        let x = (2.0 * x).abs();
+        if x > 2.0 {
+            0.0
+        } else if x > 1.0 {
+            ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x
+             + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+        } else {
+            ((12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+             + (6.0 - 2.0 * b)) / 6.0
+        }
+    }
+}
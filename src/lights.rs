@@ -1,55 +1,257 @@
 use crate::vec::Vec3;
+use crate::scene::Scene;
+use crate::material::{Material,SurfacePoint};
+use crate::sampling::{concentric_disk,orthonormal_basis,stratified_square};
 use serde::{Serialize,Deserialize};
+use rand::RngCore;
 
-#[typetag::serde(tag="type")]
-pub trait Light: Sync + Send {
-    fn intensity(&self, point: &Vec3, norm: &Vec3) -> f32;
-    fn specular(&self, point: &Vec3, norm: &Vec3, eye: &Vec3, s: u32) -> f32;
-    fn point(&self) -> Option<&Vec3> {
-        None
+use std::f32::consts::PI;
+
+/// Light arriving at a shaded point from one position on a light
+pub struct LightSample {
+    /// Unit direction from the shaded point toward the light
+    pub direction: Vec3,
+    /// Distance the shadow ray is traced to, `None` for lights at infinity
+    pub distance: Option<f32>,
+    /// Linear RGB
+    pub intensity: [f32; 3],
+}
+
+impl LightSample {
+    /// Sample of the light at `position`, seen from `point`
+    pub fn towards(point: &Vec3, position: &Vec3, intensity: [f32; 3]) -> LightSample {
+        let to_light = position - point;
+        let distance = to_light.length();
+        LightSample {
+            direction: to_light / distance,
+            distance: Some(distance),
+            intensity,
+        }
+    }
+}
+
+/// How the light of a positioned light without `power` weakens with distance `d`
+#[derive(Serialize,Deserialize,Default,Clone,Copy,PartialEq,Debug)]
+#[serde(rename_all="snake_case")]
+pub enum Falloff {
+    /// Same intensity at any distance
+    #[default]
+    None,
+    /// intensity / d², like real lights
+    InverseSquare,
+    /// intensity / (constant + linear * d + quadratic * d²)
+    Attenuation {
+        #[serde(default = "Falloff::default_constant")]
+        constant: f32,
+        #[serde(default)]
+        linear: f32,
+        #[serde(default)]
+        quadratic: f32,
+    },
+}
+
+impl Falloff {
+    fn factor(&self, distance: f32) -> f32 {
+        match *self {
+            Falloff::None => 1.0,
+            Falloff::InverseSquare => 1.0 / (distance * distance),
+            Falloff::Attenuation{constant, linear, quadratic} =>
+                1.0 / (constant + linear * distance + quadratic * distance * distance),
+        }
+    }
+
+    pub fn default_constant() -> f32 {
+        1.0
     }
 }
 
+/// Color and strength of a light
 #[derive(Serialize,Deserialize)]
-pub struct PointLight {
-    position: Vec3,
+pub struct Emission {
+    /// Linear RGB, multiplied by `intensity` or derived from `power`
+    #[serde(default = "Emission::default_color")]
+    color: [f32; 3],
+    #[serde(default = "Emission::default_intensity")]
     intensity: f32,
+    /// Radiant flux in watts, replaces `intensity` and makes the light fall off
+    /// with the inverse square of the distance like a real one. Shaded points get
+    /// the irradiance over pi, what a white diffuse surface reflects as radiance
+    #[serde(default)]
+    power: Option<f32>,
+    /// Falloff of `intensity`, ignored with `power`
+    #[serde(default)]
+    falloff: Falloff,
+    /// Distance from the light center where its light fades out. Points farther away
+    /// skip the light entirely, without tracing shadow rays.
+    #[serde(default)]
+    range: Option<f32>,
 }
 
-#[typetag::serde(name="point")]
-impl Light for PointLight {
-    fn intensity(&self, point: &Vec3, norm: &Vec3) -> f32 {
-        let direction = &self.position - point;
-        if (Vec3::new(0.0, 0.0, 3.5) - point).length() < 1e-4 {
-            println!("{} {}", direction, &direction.cos(&norm) * self.intensity);
+impl Emission {
+    #[allow(dead_code)]
+    pub fn new(color: [f32; 3], intensity: f32) -> Emission {
+        Emission {
+            color,
+            intensity,
+            power: None,
+            falloff: Falloff::None,
+            range: None,
+        }
+    }
+
+    /// `color` scaled by `intensity`
+    fn rgb(&self, intensity: f32) -> [f32; 3] {
+        self.color.map(|c| c * intensity)
+    }
+
+    /// Whether the light reaches points at `distance` from its center
+    fn reaches(&self, distance: f32) -> bool {
+        self.range.is_none_or(|range| distance < range)
+    }
+
+    /// Smooth fade to zero at `range`, so the cutoff leaves no visible edge
+    fn window(&self, distance: f32) -> f32 {
+        match self.range {
+            Some(range) => (1.0 - (distance / range).powi(4)).max(0.0).powi(2),
+            None => 1.0,
+        }
+    }
+
+    /// Light from a point-like source reaching `distance` away; `spread` is the solid angle
+    /// the source sends its power into
+    fn point_intensity(&self, distance: f32, spread: f32) -> [f32; 3] {
+        let intensity = match self.power {
+            Some(power) => power / spread / (PI * distance * distance),
+            None => self.intensity * self.falloff.factor(distance),
+        };
+        self.rgb(intensity * self.window(distance))
+    }
+
+    pub fn default_color() -> [f32; 3] {
+        [1.0, 1.0, 1.0]
+    }
+
+    pub fn default_intensity() -> f32 {
+        1.0
+    }
+}
+
+#[typetag::serde(tag="type")]
+pub trait Light: Sync + Send {
+    /// Directions toward the light seen from `point`, one per shadow ray.
+    /// Their contributions are averaged, lights objects can't block have none.
+    fn samples(&self, _point: &Vec3, _rng: &mut dyn RngCore) -> Vec<LightSample> {
+        vec![]
+    }
+    /// Light reaching every point from all directions, ignoring shadows
+    fn ambient(&self) -> [f32; 3] {
+        [0.0; 3]
+    }
+    /// Radiance reaching a ray that leaves the scene in `direction`
+    fn background(&self, _direction: &Vec3) -> [f32; 3] {
+        [0.0; 3]
+    }
+}
+
+/// Light the lights that cast shadows send toward `eye`, the direction of the incoming ray,
+/// off `surface` with `material`. `surface.norm` must face `eye`. With `glossy_traced` the
+/// environment and glowing objects leave out the highlight, which sees them along its own rays.
+pub fn direct_light(lights: &[Box<dyn Light>], objects: &Scene,
+                    surface: &SurfacePoint, eye: &Vec3, material: &dyn Material,
+                    glossy_traced: bool, mut rng: &mut dyn RngCore) -> [f32; 3] {
+    let point = &surface.point;
+    let norm = &surface.norm;
+    let over_point = point + norm * 1e-4;
+    let mut intensity = [0.0; 3];
+    // Rays can hit the environment and glowing objects, but not the lights
+    let hittable_reflected = |direction: &Vec3| if glossy_traced {
+        material.diffuse_reflected(surface, direction, eye)
+    } else {
+        material.reflected(surface, direction, eye)
+    };
+    for light in lights {
+        let samples = light.samples(point, rng);
+        for sample in &samples {
+            if objects.in_shadow(&over_point, &sample.direction, sample.distance) {
+                continue;
+            }
+            let reflected = material.reflected(surface, &sample.direction, eye);
+            for ((c, factor), light_c) in intensity.iter_mut().zip(reflected).zip(sample.intensity) {
+                *c += factor * light_c / samples.len() as f32;
+            }
         }
-        f32::max(direction.cos(&norm), 0.0) * self.intensity
     }
 
-    fn specular(&self, point: &Vec3, norm: &Vec3, eye: &Vec3, s: u32) -> f32 {
-        let light_direction = &self.position - point;
-        let norm_dot_direction = norm.norm().dot(&light_direction);
+    // Environment, sampled in proportion to its brightness where it supports that
+    if let Some(environment) = objects.environment() {
+        let count = environment.samples();
+        for (u, v) in stratified_square(count, &mut rng) {
+            let (direction, density) = environment.sample(u, v);
+            let cos_surface = direction.dot(norm);
+            if cos_surface <= 0.0 || density <= 0.0 || objects.in_shadow(&over_point, &direction, None) {
+                continue;
+            }
+            let scale = PI * density * count as f32;
+            let reflected = hittable_reflected(&direction);
+            for ((c, factor), light_c) in intensity.iter_mut().zip(reflected).zip(environment.radiance(&direction)) {
+                *c += factor * light_c / scale;
+            }
+        }
+    }
 
-        let r = norm.norm() * 2.0 * norm_dot_direction - light_direction;
+    // Glowing objects, sampled uniformly over their surface area
+    for emitter in objects.emitters() {
+        let emitted = emitter.emitted();
+        let count = emitter.emission_samples;
+        for (u, v) in stratified_square(count, &mut rng) {
+            let Some(sample) = emitter.sample_surface(u, v) else {
+                continue;
+            };
+            let to_light = &sample.point - point;
+            let distance = to_light.length();
+            let direction = to_light / distance;
+            let cos_surface = direction.dot(norm);
+            // Stop the shadow ray short of the emitting surface itself
+            if cos_surface <= 0.0 || objects.in_shadow(&over_point, &direction, Some(distance * (1.0 - 1e-3))) {
+                continue;
+            }
 
-        let eye_cos_r = -eye.cos(&r);
-        if eye_cos_r <= 0.0 {
-            return 0.0;
+            // Solid angle of the sampled area over pi, matching the diffuse bounces of `path_trace`
+            let cos_light = sample.norm.dot(&direction).abs();
+            let scale = cos_light * sample.area / (PI * distance * distance) / count as f32;
+            let reflected = hittable_reflected(&direction);
+            for ((c, factor), light_c) in intensity.iter_mut().zip(reflected).zip(emitted) {
+                *c += factor * light_c * scale;
+            }
         }
-        self.intensity * eye_cos_r.powf(s as f32)
     }
+    intensity
+}
+
+#[derive(Serialize,Deserialize)]
+pub struct PointLight {
+    position: Vec3,
+    #[serde(flatten)]
+    emission: Emission,
+}
 
-    fn point(&self) -> Option<&Vec3> {
-        Some(&self.position)
+#[typetag::serde(name="point")]
+impl Light for PointLight {
+    fn samples(&self, point: &Vec3, _rng: &mut dyn RngCore) -> Vec<LightSample> {
+        let distance = (&self.position - point).length();
+        if !self.emission.reaches(distance) {
+            return vec![];
+        }
+        vec![LightSample::towards(point, &self.position, self.emission.point_intensity(distance, 4.0 * PI))]
     }
 }
 
 impl PointLight {
     #[allow(dead_code)]
-    pub fn new(position: Vec3, intensity: f32) -> PointLight {
+    pub fn new(position: Vec3, emission: Emission) -> PointLight {
         PointLight {
             position,
-            intensity,
+            emission,
         }
     }
 }
@@ -57,22 +259,347 @@ impl PointLight {
 #[derive(Serialize,Deserialize)]
 pub struct AmbientLight {
     intensity: f32,
+    #[serde(default = "Emission::default_color")]
+    color: [f32; 3],
 }
 
 #[typetag::serde(name="ambient")]
 impl Light for AmbientLight {
-    fn intensity(&self, _point: &Vec3, _norm: &Vec3) -> f32 {
-        self.intensity
+    fn ambient(&self) -> [f32; 3] {
+        self.color.map(|c| c * self.intensity)
     }
 
-    fn specular(&self, _point: &Vec3, _norm: &Vec3, _eye: &Vec3, _s: u32) -> f32 {
-        0.0
+    fn background(&self, _direction: &Vec3) -> [f32; 3] {
+        self.ambient()
     }
 }
 
 impl AmbientLight {
     #[allow(dead_code)]
-    pub fn new(intensity: f32) -> AmbientLight {
-        AmbientLight{intensity}
+    pub fn new(intensity: f32, color: [f32; 3]) -> AmbientLight {
+        AmbientLight{intensity, color}
+    }
+}
+
+/// Light from infinitely far away, such as the sun, hitting everything from the same direction
+#[derive(Serialize,Deserialize)]
+pub struct DirectionalLight {
+    /// Direction the light travels in
+    direction: Vec3,
+    /// Radiance a white diffuse surface facing the light reflects,
+    /// the irradiance in W/m² over pi for physical scenes
+    intensity: f32,
+    #[serde(default = "Emission::default_color")]
+    color: [f32; 3],
+}
+
+#[typetag::serde(name="directional")]
+impl Light for DirectionalLight {
+    fn samples(&self, _point: &Vec3, _rng: &mut dyn RngCore) -> Vec<LightSample> {
+        let intensity = self.color.map(|c| c * self.intensity);
+        vec![LightSample{direction: -self.direction.norm(), distance: None, intensity}]
+    }
+}
+
+impl DirectionalLight {
+    #[allow(dead_code)]
+    pub fn new(direction: Vec3, intensity: f32, color: [f32; 3]) -> DirectionalLight {
+        DirectionalLight{direction, intensity, color}
+    }
+}
+
+/// Point light shining into a cone around `direction`. Full intensity inside `inner_angle`,
+/// fading to nothing at `outer_angle`, both measured from the axis in degrees.
+#[derive(Serialize,Deserialize)]
+pub struct SpotLight {
+    position: Vec3,
+    direction: Vec3,
+    #[serde(flatten)]
+    emission: Emission,
+    #[serde(default = "SpotLight::default_inner_angle")]
+    inner_angle: f32,
+    #[serde(default = "SpotLight::default_outer_angle")]
+    outer_angle: f32,
+    /// Exponent shaping the fade between the cones, larger values darken the edge faster
+    #[serde(default = "SpotLight::default_edge_exponent")]
+    edge_exponent: f32,
+}
+
+#[typetag::serde(name="spot")]
+impl Light for SpotLight {
+    fn samples(&self, point: &Vec3, _rng: &mut dyn RngCore) -> Vec<LightSample> {
+        let to_point = point - &self.position;
+        let distance = to_point.length();
+        let cos_angle = to_point.cos(&self.direction);
+        let cos_inner = self.inner_angle.to_radians().cos();
+        let cos_outer = self.outer_angle.to_radians().cos();
+        if cos_angle <= cos_outer || !self.emission.reaches(distance) {
+            return vec![];
+        }
+
+        let t = ((cos_angle - cos_outer) / f32::max(cos_inner - cos_outer, 1e-6)).min(1.0);
+        let smooth = (t * t * (3.0 - 2.0 * t)).powf(self.edge_exponent);
+        // Power spread over the cone, counting the fading edge as half
+        let spread = 2.0 * PI * (1.0 - 0.5 * (cos_inner + cos_outer));
+        let intensity = self.emission.point_intensity(distance, spread).map(|c| c * smooth);
+        vec![LightSample::towards(point, &self.position, intensity)]
+    }
+}
+
+impl SpotLight {
+    pub fn default_inner_angle() -> f32 {
+        30.0
+    }
+
+    pub fn default_outer_angle() -> f32 {
+        45.0
+    }
+
+    pub fn default_edge_exponent() -> f32 {
+        1.0
+    }
+}
+
+/// Light arriving from a sample on an emitting surface of `area`, `distance` away.
+/// `cos_light` is the angle between the surface normal and the direction to the shaded point,
+/// surfaces emit from both sides. Without `power` and falloff it is as bright
+/// as a point light of the same `intensity` seen head-on.
+/// `center_distance` to the middle of the light drives the fade toward `range`.
+fn surface_intensity(emission: &Emission, area: f32, distance: f32, cos_light: f32, center_distance: f32) -> [f32; 3] {
+    let intensity = match emission.power {
+        Some(power) => {
+            let radiance = power / (2.0 * PI * area);
+            radiance * area * cos_light.abs() / (PI * distance * distance)
+        },
+        None => emission.intensity * cos_light.abs() * emission.falloff.factor(distance),
+    };
+    emission.rgb(intensity * emission.window(center_distance))
+}
+
+/// Parallelogram centered at `position` and spanned by the two edges
+#[derive(Serialize,Deserialize)]
+pub struct RectangleLight {
+    position: Vec3,
+    edge1: Vec3,
+    edge2: Vec3,
+    #[serde(flatten)]
+    emission: Emission,
+    /// Shadow rays per shaded point
+    #[serde(default = "RectangleLight::default_samples")]
+    samples: u32,
+}
+
+#[typetag::serde(name="rectangle")]
+impl Light for RectangleLight {
+    fn samples(&self, point: &Vec3, mut rng: &mut dyn RngCore) -> Vec<LightSample> {
+        let norm = self.edge1.cross(&self.edge2);
+        let area = norm.length();
+        let center_distance = (point - &self.position).length();
+        if !self.emission.reaches(center_distance) {
+            return vec![];
+        }
+        stratified_square(self.samples, &mut rng).into_iter().map(|(u, v)| {
+            let position = &self.position + &self.edge1 * (u - 0.5) + &self.edge2 * (v - 0.5);
+            let to_point = point - &position;
+            let intensity = surface_intensity(&self.emission, area, to_point.length(), to_point.cos(&norm), center_distance);
+            LightSample::towards(point, &position, intensity)
+        }).collect()
+    }
+}
+
+impl RectangleLight {
+    pub fn default_samples() -> u32 {
+        16
+    }
+}
+
+/// Disk facing along `normal`
+#[derive(Serialize,Deserialize)]
+pub struct DiskLight {
+    position: Vec3,
+    normal: Vec3,
+    radius: f32,
+    #[serde(flatten)]
+    emission: Emission,
+    /// Shadow rays per shaded point
+    #[serde(default = "DiskLight::default_samples")]
+    samples: u32,
+}
+
+#[typetag::serde(name="disk")]
+impl Light for DiskLight {
+    fn samples(&self, point: &Vec3, mut rng: &mut dyn RngCore) -> Vec<LightSample> {
+        let norm = self.normal.norm();
+        let (tangent, bitangent) = orthonormal_basis(&norm);
+        let area = PI * self.radius * self.radius;
+        let center_distance = (point - &self.position).length();
+        if !self.emission.reaches(center_distance) {
+            return vec![];
+        }
+        stratified_square(self.samples, &mut rng).into_iter().map(|(u, v)| {
+            let (x, y) = concentric_disk(u, v);
+            let position = &self.position + &tangent * (x * self.radius) + &bitangent * (y * self.radius);
+            let to_point = point - &position;
+            let intensity = surface_intensity(&self.emission, area, to_point.length(), to_point.cos(&norm), center_distance);
+            LightSample::towards(point, &position, intensity)
+        }).collect()
+    }
+}
+
+impl DiskLight {
+    pub fn default_samples() -> u32 {
+        16
+    }
+}
+
+/// Glowing ball, only the cap visible from the shaded point is sampled
+#[derive(Serialize,Deserialize)]
+pub struct SphereLight {
+    position: Vec3,
+    radius: f32,
+    #[serde(flatten)]
+    emission: Emission,
+    /// Shadow rays per shaded point
+    #[serde(default = "SphereLight::default_samples")]
+    samples: u32,
+}
+
+#[typetag::serde(name="sphere")]
+impl Light for SphereLight {
+    fn samples(&self, point: &Vec3, mut rng: &mut dyn RngCore) -> Vec<LightSample> {
+        let to_center = &self.position - point;
+        let distance = to_center.length();
+        if !self.emission.reaches(distance) {
+            return vec![];
+        }
+        let axis = to_center / distance;
+        let (tangent, bitangent) = orthonormal_basis(&axis);
+        // Inside the light every direction hits it
+        let cos_max = if distance > self.radius {
+            (1.0 - (self.radius / distance).powi(2)).sqrt()
+        } else {
+            -1.0
+        };
+        let intensity = match self.emission.power {
+            // Radiance of the surface times the solid angle the samples cover, over pi
+            Some(power) => {
+                let radiance = power / (4.0 * PI * PI * self.radius * self.radius);
+                self.emission.rgb(radiance * 2.0 * (1.0 - cos_max) * self.emission.window(distance))
+            },
+            // Falloff is measured from the center, as for a point light
+            None => self.emission.point_intensity(distance, 4.0 * PI),
+        };
+
+        stratified_square(self.samples, &mut rng).into_iter().map(|(u, v)| {
+            // Uniform over the cone of directions toward the sphere
+            let cos_theta = 1.0 - u * (1.0 - cos_max);
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let phi = 2.0 * PI * v;
+            let direction = &tangent * (sin_theta * phi.cos()) + &bitangent * (sin_theta * phi.sin()) + &axis * cos_theta;
+
+            // Nearest intersection with the sphere along that direction
+            let b = distance * cos_theta;
+            let c = distance * distance - self.radius * self.radius;
+            let root = (b * b - c).max(0.0).sqrt();
+            let t = if c > 0.0 { b - root } else { b + root };
+            LightSample{direction, distance: Some(t), intensity}
+        }).collect()
+    }
+}
+
+impl SphereLight {
+    pub fn default_samples() -> u32 {
+        16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::PhongMaterial;
+    use rand::SeedableRng;
+    use rand::rngs::SmallRng;
+    use serde_json::json;
+
+    /// Every key of `Emission`, which the light types take flattened next to their own
+    fn emission_keys() -> serde_json::Value {
+        json!({
+            "color": [1.0, 0.5, 0.25],
+            "intensity": 2.0,
+            "power": 100.0,
+            "falloff": {"attenuation": {"linear": 0.5}},
+            "range": 10.0
+        })
+    }
+
+    fn with_emission_keys(mut light: serde_json::Value) -> serde_json::Value {
+        if let (Some(light), Some(emission)) = (light.as_object_mut(), emission_keys().as_object()) {
+            light.extend(emission.clone());
+        }
+        light
+    }
+
+    fn assert_emission_keys(emission: &Emission) {
+        assert_eq!(emission.color, [1.0, 0.5, 0.25]);
+        assert_eq!(emission.intensity, 2.0);
+        assert_eq!(emission.power, Some(100.0));
+        assert_eq!(emission.falloff, Falloff::Attenuation{constant: 1.0, linear: 0.5, quadratic: 0.0});
+        assert_eq!(emission.range, Some(10.0));
+    }
+
+    #[test]
+    fn lights_parse_every_emission_key() {
+        let point: PointLight = serde_json::from_value(with_emission_keys(json!({
+            "position": [0.0, 0.0, 0.0]
+        }))).unwrap();
+        assert_emission_keys(&point.emission);
+
+        let spot: SpotLight = serde_json::from_value(with_emission_keys(json!({
+            "position": [0.0, 0.0, 0.0], "direction": [0.0, 1.0, 0.0], "edge_exponent": 3.0
+        }))).unwrap();
+        assert_emission_keys(&spot.emission);
+        assert_eq!(spot.edge_exponent, 3.0);
+
+        let rectangle: RectangleLight = serde_json::from_value(with_emission_keys(json!({
+            "position": [0.0, 0.0, 0.0], "edge1": [1.0, 0.0, 0.0], "edge2": [0.0, 0.0, 1.0]
+        }))).unwrap();
+        assert_emission_keys(&rectangle.emission);
+
+        let disk: DiskLight = serde_json::from_value(with_emission_keys(json!({
+            "position": [0.0, 0.0, 0.0], "normal": [0.0, 1.0, 0.0], "radius": 0.5
+        }))).unwrap();
+        assert_emission_keys(&disk.emission);
+        assert_eq!(disk.radius, 0.5);
+
+        let sphere: SphereLight = serde_json::from_value(with_emission_keys(json!({
+            "position": [0.0, 0.0, 0.0], "radius": 0.5
+        }))).unwrap();
+        assert_emission_keys(&sphere.emission);
+        assert_eq!(sphere.radius, 0.5);
+    }
+
+    #[test]
+    fn power_gives_physical_radiance() {
+        let mut scene = Scene::default();
+        scene.init();
+        let lights: Vec<Box<dyn Light>> = serde_json::from_value(json!([
+            {"type": "point", "position": [0.0, -1.0, 0.0], "power": 4.0 * PI}
+        ])).unwrap();
+        let mut material = PhongMaterial::new([1.0, 1.0, 1.0], 0, 0.0, 0.0, 1.0);
+        material.init();
+        let surface = SurfacePoint {
+            point: Vec3::new(0.0, 0.0, 0.0),
+            object_point: Vec3::new(0.0, 0.0, 0.0),
+            norm: Vec3::new(0.0, -1.0, 0.0),
+            uv: None,
+            footprint: 0.0,
+            derivatives: None,
+            color: None,
+        };
+        let mut rng = SmallRng::seed_from_u64(1);
+        let radiance = direct_light(&lights, &scene, &surface, &Vec3::new(0.0, 1.0, 0.0), &material, false, &mut rng);
+        for c in radiance {
+            assert!((c - 1.0 / PI).abs() < 1e-5, "{c}");
+        }
     }
 }
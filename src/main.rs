@@ -1,5 +1,3 @@
-use image;
-
 use std::fs;
 
 mod vec;
@@ -14,130 +12,199 @@ mod shapes {
     pub mod obj;
 }
 mod object;
+mod material;
+mod texture;
+mod procedural;
+mod bump;
+mod scene;
+mod bvh;
 mod lights;
+mod sampling;
+mod filter;
+mod camera;
+mod output;
+mod tonemap;
+mod path_tracer;
+mod environment;
 
 use vec::Vec3;
-use matrix::Matrix33;
-use shapes::shape::IntersectionResult;
-use object::Object;
-use lights::Light;
+use scene::Scene;
+use lights::{Light,direct_light};
+use material::SurfacePoint;
+use path_tracer::path_trace;
+use filter::{Filter,BoxFilter};
+use sampling::{glossy_reflection,stratified_square,uniform_square};
+use tonemap::ToneMap;
+use camera::{Camera,PerspectiveCamera};
+use environment::Environment;
 
 //use indicatif::{ProgressBar,ProgressStyle};
 use clap::Parser;
 use serde::{Serialize,Deserialize};
 use itertools::iproduct;
 
-use indicatif::{ProgressBar, ParallelProgressIterator, ProgressStyle, ProgressIterator};
+use indicatif::{ParallelProgressIterator, ProgressStyle};
 use rayon::iter::{ParallelIterator, IntoParallelRefIterator};
-use rayon::prelude::*;
-
-fn intersect<'a>(start: &Vec3, direction: &Vec3,
-                 objects: &'a Vec<Object>,
-                 t_min: Option<f32>, t_max: Option<f32>) -> Option<(IntersectionResult, &'a Object)> {
-    let mut best_result: Option<(IntersectionResult, &Object)> = None;
-    for object in objects {
-        best_result = match object.intersects(start, direction) {
-            None => best_result,
-            Some((intersection, n_object)) => {
-                if (t_min.is_none() || intersection.distance >= t_min.unwrap())
-                    && (t_max.is_none() || intersection.distance <= t_max.unwrap())
-                    && (best_result.is_none() || intersection.distance < best_result.as_ref().unwrap().0.distance) {
-                        Some((intersection, n_object))
-                } else {
-                    best_result
-                }
-            }
-        }
-    }
-    best_result
-}
+use rand::{Rng,SeedableRng};
+use rand::seq::SliceRandom;
+use rand::rngs::SmallRng;
 
 fn reflect_vec(ray: &Vec3, norm: &Vec3) -> Vec3 {
     norm * 2.0 * norm.dot(ray) - ray
 }
 
-fn ray_trace(start: &Vec3, direction: &Vec3,
-             objects: &Vec<Object>,
-             lights: &Vec<Box<dyn Light>>,
-             t_min: Option<f32>, t_max: Option<f32>,
-             depth: u16) -> Option<[f32; 3]> {
+/// Splits a ray hitting a surface between media with refractive indices `n1` and `n2`.
+/// `ray` and `norm` must be normalized, with `norm` facing against `ray`.
+/// Returns the Schlick reflectance and the refracted direction, which is `None`
+/// on total internal reflection.
+fn fresnel(ray: &Vec3, norm: &Vec3, n1: f32, n2: f32) -> (f32, Option<Vec3>) {
+    let eta = n1 / n2;
+    let cos_i = -norm.dot(ray);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t > 1.0 {
+        return (1.0, None);
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let cos = if n1 > n2 { cos_t } else { cos_i };
+    let r0 = ((n1 - n2) / (n1 + n2)).powi(2);
+    let reflectance = r0 + (1.0 - r0) * (1.0 - cos).powi(5);
+    let refracted = ray * eta + norm * (eta * cos_i - cos_t);
+    (reflectance, Some(refracted.norm()))
+}
+
+/// Color seen along the ray. Rough mirror reflections average several rays when `split` is set,
+/// the rays they trace reflect off further rough mirrors once so ray counts don't multiply.
+#[allow(clippy::too_many_arguments)]
+fn ray_trace<R: Rng>(start: &Vec3, direction: &Vec3,
+                     objects: &Scene,
+                     lights: &Vec<Box<dyn Light>>,
+                     t_min: Option<f32>,
+                     depth: u16,
+                     split: bool,
+                     rng: &mut R) -> Option<[f32; 3]> {
 
     if depth == 0 {
         return None;
     }
 
-    let mut intensity = 0.0;
-    let best_intersection  = intersect(&start, &direction, &objects, t_min, t_max);
+    let best_intersection  = objects.intersect(start, direction, t_min, None);
 
     match best_intersection {
         Some((intersection, object)) => {
-            let mut result_color = match intersection.color {
-                Some(color) => color,
-                None => object.color
-            };
             let point = start + direction * intersection.distance;
+            let direction = direction.norm();
 
+            // Shapes report outward normals, shading needs the one facing the ray
+            let mut norm = intersection.norm.norm();
+            let inside = norm.dot(&direction) > 0.0;
+            if inside {
+                norm = -norm;
+            }
+            let eps = 1e-4;
+            let over_point = &point + &norm * eps;
+            let under_point = &point - &norm * eps;
+
+            let material = object.material();
+            let ray_width = objects.pixel_spread() * (&point - start).length();
+            let surface = SurfacePoint::new(object, &intersection, point, norm.clone(), ray_width);
+            // Bumps tilt the normal for shading, rays still leave from the geometric surface
+            let norm = surface.norm.clone();
+            let base_color = material.albedo(&surface);
+            let glossy_traced = material.traces_glossy();
+            let mut result_color = direct_light(lights, objects, &surface, &direction, material, glossy_traced, rng);
             for light in lights {
-                let intersects = if let Some(light_point) = light.point() {
-                    let to_light = light_point - &point;
-                    match intersect(&point, &to_light, &objects, Some(1e-4), Some(1.0)) {
-                        Some((light_intersection, _light_object)) => light_intersection.distance < 1.0,
-                        None => false
+                let ambient = light.ambient();
+                for i in 0..3 {
+                    result_color[i] += base_color[i] * ambient[i];
+                }
+            }
+
+            let reflection = material.reflection(&surface);
+            let transparency = material.transparency(&surface);
+            let (reflectance, refracted) = if transparency > 0.0 {
+                if inside {
+                    fresnel(&direction, &norm, material.refractive(&surface), 1.0)
+                } else {
+                    fresnel(&direction, &norm, 1.0, material.refractive(&surface))
+                }
+            } else {
+                (0.0, None)
+            };
+
+            let local_weight = (1.0 - transparency) * (1.0 - reflection);
+            let reflect_weight = (1.0 - transparency) * reflection + transparency * reflectance;
+            let refract_weight = transparency * (1.0 - reflectance);
+
+            // Highlights mirroring the scene follow rays picked from the material's own lobe
+            if glossy_traced {
+                let count = if split { material.glossy_samples().max(1) } else { 1 };
+                for (u, v) in stratified_square(count, rng) {
+                    let Some((reflected, weight)) = material.sample_glossy(&surface, &direction, u, v) else {
+                        continue;
+                    };
+                    let glossy_color = ray_trace(&over_point, &reflected, objects, lights, Some(eps), depth-1, false, rng);
+                    if let Some(glossy_color) = glossy_color {
+                        for i in 0..3 {
+                            result_color[i] += glossy_color[i] * weight[i] / count as f32;
+                        }
                     }
+                }
+            }
+
+            for c in result_color.iter_mut() {
+                *c *= local_weight;
+            }
+
+            if reflect_weight > 0.0 {
+                let glossiness = material.glossiness(&surface);
+                let reflected: Vec<Vec3> = if glossiness > 0.0 {
+                    let count = if split { material.glossy_samples().max(1) } else { 1 };
+                    stratified_square(count, rng).into_iter()
+                        .map(|(u, v)| glossy_reflection(&direction, &norm, glossiness, u, v))
+                        .collect()
                 } else {
-                    false
+                    vec![reflect_vec(&(-&direction), &norm).norm()]
                 };
-                if !intersects {
-                    intensity += light.intensity(&point, &intersection.norm);
-                    if object.specular > 0 {
-                        intensity += light.specular(&point, &intersection.norm, &direction, object.specular);
+                let split = split && glossiness == 0.0;
+                let weight = reflect_weight / reflected.len() as f32;
+                for reflected in &reflected {
+                    let reflect_color = ray_trace(&over_point, reflected, objects, lights, Some(eps), depth-1, split, rng);
+                    if let Some(reflected_color) = reflect_color {
+                        for i in 0..3 {
+                            result_color[i] += reflected_color[i] * weight;
+                        }
                     }
                 }
             }
-            result_color[0] = result_color[0] * intensity;
-            result_color[1] = result_color[1] * intensity;
-            result_color[2] = result_color[2] * intensity;
-
-            let reflection = object.reflection;
-            if reflection != 0.0 {
-                result_color[0] = result_color[0] * (1.0 - reflection);
-                result_color[1] = result_color[1] * (1.0 - reflection);
-                result_color[2] = result_color[2] * (1.0 - reflection);
-
-                let reflected = reflect_vec(&(-direction), &intersection.norm.norm()).norm();
-                let reflect_color = ray_trace(&point, &reflected, objects, lights, Some(1e-4), None, depth-1);
-                if let Some(reflected_color) = reflect_color {
-                    result_color[0] += reflected_color[0] * reflection;
-                    result_color[1] += reflected_color[1] * reflection;
-                    result_color[2] += reflected_color[2] * reflection;
+
+            if let Some(refracted) = refracted {
+                if refract_weight > 0.0 {
+                    let refract_color = ray_trace(&under_point, &refracted, objects, lights, Some(eps), depth-1, split, rng);
+                    if let Some(refracted_color) = refract_color {
+                        // Light passing through the object is tinted by its color
+                        for i in 0..3 {
+                            result_color[i] += refracted_color[i] * base_color[i] * refract_weight;
+                        }
+                    }
                 }
             }
+            let emitted = object.emitted();
+            for i in 0..3 {
+                result_color[i] += emitted[i];
+            }
             Some(result_color)
         },
-        None => None
+        None => objects.background(&direction.norm())
     }
 }
 
-fn rotate_view(x_phi: f32, y_phi: f32, z_phi: f32, eye: vec::Vec3) -> vec::Vec3 {
-    let x_matrix = Matrix33::new([1.0, 0.0, 0.0,
-                                  0.0, f32::cos(x_phi), -f32::sin(x_phi),
-                                  0.0, f32::sin(x_phi), f32::cos(x_phi)]);
-    let y_matrix = Matrix33::new([f32::cos(y_phi), 0.0, f32::sin(y_phi),
-                                  0.0, 1.0, 0.0,
-                                  -f32::sin(y_phi), 0.0, f32::cos(y_phi)]);
-    let z_matrix = Matrix33::new([f32::cos(z_phi), -f32::sin(z_phi), 0.0,
-                                  f32::sin(z_phi), f32::cos(z_phi), 0.0,
-                                  0.0, 0.0, 1.0]);
-    eye * x_matrix * y_matrix * z_matrix
-}
-
 #[derive(Parser,Debug)]
 struct Args {
     /// Path to config file
     #[arg(short, long)]
     config: String,
 
-    /// Path to output file
+    /// Path to output file, `.exr` and `.hdr` keep high dynamic range
     #[arg(short, long)]
     output_file: String,
 
@@ -146,62 +213,155 @@ struct Args {
     print_debug_objects: bool,
 }
 
+#[derive(Serialize,Deserialize,Default,Clone,Copy,PartialEq,Debug)]
+#[serde(rename_all="lowercase")]
+enum Integrator {
+    /// Recursive tracer with direct lighting and perfect mirrors/refraction
+    #[default]
+    Whitted,
+    /// Monte Carlo path tracer with diffuse interreflection
+    Path,
+}
+
+#[derive(Serialize,Deserialize,Default,Clone,Copy,PartialEq,Debug)]
+#[serde(rename_all="lowercase")]
+enum PixelSampler {
+    /// Jittered samples on a grid over the filter footprint
+    #[default]
+    Stratified,
+    /// Independent uniform samples over the filter footprint
+    Random,
+}
+
 #[derive(Serialize,Deserialize)]
 struct Config {
     img_size: (i32, i32),
+    /// Max recursion depth for `whitted`, bounces before Russian roulette for `path`
     reflection_depth: u16,
     #[serde(default)]
+    integrator: Integrator,
+    #[serde(default = "Config::default_samples_per_pixel")]
+    samples_per_pixel: u32,
+    #[serde(default)]
+    pixel_sampler: PixelSampler,
+    #[serde(default = "Config::default_filter")]
+    filter: Box<dyn Filter>,
+    #[serde(default)]
+    tonemap: ToneMap,
+    #[serde(default)]
+    camera: Option<Box<dyn Camera>>,
+    /// Camera position when there is no `camera` block
+    #[serde(default)]
     start: Vec3,
+    /// Camera rotation in radians when there is no `camera` block
     #[serde(default)]
-    // TODO: convert from radians
     view_angle: Vec3,
 
+    /// Surroundings seen where rays miss every object, they light the scene as well
+    #[serde(default)]
+    environment: Option<Box<dyn Environment>>,
+
     // TODO: Union shape?
-    objects: Vec<Object>,
+    objects: Scene,
     lights: Vec<Box<dyn Light>>,
 }
 
-fn process_pixel(x: i32, y: i32, config: &Config,
-                 viewport_size: (f32, f32),
-                 z_dist: f32) -> (i32, i32, [u8; 3]) {
+impl Config {
+    fn default_samples_per_pixel() -> u32 {
+        1
+    }
+
+    fn default_filter() -> Box<dyn Filter> {
+        Box::new(BoxFilter::new(BoxFilter::default_radius()))
+    }
+
+    fn init(&mut self) {
+        let mut camera = self.camera.take()
+            .unwrap_or_else(|| Box::new(PerspectiveCamera::from_view(&self.start, &self.view_angle)));
+        if let Some(environment) = &self.environment {
+            self.lights.extend(environment.lights());
+        }
+        self.objects.set_environment(self.environment.take());
+        self.objects.init();
+
+        camera.init(self.img_size, &self.objects);
+        self.objects.set_pixel_spread(camera.pixel_spread());
+        self.camera = Some(camera);
+    }
+
+    fn camera(&self) -> &dyn Camera {
+        self.camera.as_deref().expect("Camera is set up by Config::init")
+    }
+}
+
+/// Color seen through the point `(x, y)` of the image, in pixels, with the lens sample `lens`
+fn trace_sample(x: f32, y: f32, lens: (f32, f32), config: &Config, rng: &mut SmallRng) -> [f32; 3] {
     let lights = &config.lights;
     let objects = &config.objects;
 
-    let eye = Vec3::new(
-        viewport_size.0 * ((x - config.img_size.0/2) as f32) / (config.img_size.0 as f32),
-        viewport_size.1 * ((y - config.img_size.1/2) as f32) / (config.img_size.1 as f32),
-        z_dist)
-        .norm();
-
-    let eye = rotate_view(config.view_angle.x(), config.view_angle.y(), config.view_angle.z(), eye);
-    let color = ray_trace(&config.start, &eye, &objects, &lights, Some(1.0), None, config.reflection_depth);
-    (x, y, match color {
-        Some(real_color) => {
-            [(255.0 * real_color[0]) as u8,
-             (255.0 * real_color[1]) as u8,
-             (255.0 * real_color[2]) as u8]
-        },
-        None => {
-            [0, 0, 0]
+    let Some((start, direction)) = config.camera().ray(x, y, lens) else {
+        return [0.0; 3];
+    };
+    match config.integrator {
+        Integrator::Whitted => ray_trace(&start, &direction, objects, lights, Some(0.0), config.reflection_depth, true, rng)
+            .unwrap_or([0.0; 3]),
+        Integrator::Path => path_trace(&start, &direction, objects, lights, Some(0.0), config.reflection_depth, rng),
+    }
+}
+
+fn process_pixel(x: i32, y: i32, config: &Config) -> (i32, i32, [f32; 3]) {
+    let mut rng = SmallRng::seed_from_u64((y * config.img_size.0 + x) as u64);
+
+    // Samples are spread over the whole filter footprint, which may overlap neighbour pixels
+    let radius = config.filter.radius();
+    let (offsets, mut lens_samples) = if config.samples_per_pixel == 1 {
+        (vec![(0.0, 0.0)], vec![(0.5, 0.5)])
+    } else {
+        let mut square = || match config.pixel_sampler {
+            PixelSampler::Stratified => stratified_square(config.samples_per_pixel, &mut rng),
+            PixelSampler::Random => uniform_square(config.samples_per_pixel, &mut rng),
+        };
+        let offsets = square().iter().map(|(u, v)| ((2.0 * u - 1.0) * radius, (2.0 * v - 1.0) * radius)).collect();
+        (offsets, square())
+    };
+    // Pair pixel and lens strata randomly so they do not correlate
+    lens_samples.shuffle(&mut rng);
+
+    let mut color = [0.0; 3];
+    let mut weight_sum = 0.0;
+    for ((dx, dy), lens) in offsets.into_iter().zip(lens_samples) {
+        let weight = config.filter.evaluate(dx, dy);
+        if weight == 0.0 {
+            continue;
         }
-    })
+        // Pixel centers are at half-integer image coordinates
+        let sample = trace_sample(x as f32 + 0.5 + dx, y as f32 + 0.5 + dy, lens, config, &mut rng);
+        for i in 0..3 {
+            color[i] += sample[i] * weight;
+        }
+        weight_sum += weight;
+    }
+    if weight_sum != 0.0 {
+        for c in color.iter_mut() {
+            *c /= weight_sum;
+        }
+    }
+
+    (x, y, color)
 }
 
 fn main() {
     let args = Args::parse();
 
-    let viewport_size = (1.0, 1.0);
-    let z_dist = 1.0;
-
     let config_file_raw: String = fs::read_to_string(&args.config).expect("Should have been able to read config file");
-    let mut config: Config = serde_json::from_str(&config_file_raw).expect("Should have been able to parse config file");
+    let mut config_json: serde_json::Value = serde_json::from_str(&config_file_raw).expect("Should have been able to parse config file");
+    material::resolve_library(&mut config_json).unwrap_or_else(|error| panic!("Invalid materials: {}", error));
+    let mut config: Config = serde_json::from_value(config_json).expect("Should have been able to parse config file");
 
-    let mut img = image::RgbImage::new(config.img_size.0 as u32, config.img_size.1 as u32);
+    let mut img = image::Rgb32FImage::new(config.img_size.0 as u32, config.img_size.1 as u32);
 
     let start_time = std::time::Instant::now();
-    for object in config.objects.iter_mut() {
-        object.init();
-    }
+    config.init();
     let duration = start_time.elapsed();
     println!("Init took {}.{}s", duration.as_secs(), duration.subsec_millis());
 
@@ -218,12 +378,12 @@ fn main() {
         .collect::<Vec<(u32, u32)>>()
         .par_iter()
         .progress_with_style(style)
-        .map(|x| process_pixel(x.0 as i32, x.1 as i32, &config, viewport_size, z_dist))
-        .collect::<Vec<(i32, i32, [u8; 3])>>()
+        .map(|x| process_pixel(x.0 as i32, x.1 as i32, &config))
+        .collect::<Vec<(i32, i32, [f32; 3])>>()
         .iter()
         .for_each(|x| img.put_pixel(x.0 as u32, x.1 as u32, image::Rgb(x.2)));
     let duration = start_time.elapsed();
     println!("Ray tracing took {}.{}s", duration.as_secs(), duration.subsec_millis());
 
-    img.save_with_format(args.output_file, image::ImageFormat::Png).expect("Can not save result image");
+    output::save(&img, &args.output_file, &config.tonemap).expect("Can not save result image");
 }
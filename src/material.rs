@@ -0,0 +1,617 @@
+use crate::vec::Vec3;
+use crate::object::Object;
+use crate::shapes::shape::IntersectionResult;
+use crate::texture::{ColorParam,ScalarParam};
+use crate::bump::Relief;
+use crate::sampling::{cosine_hemisphere,ggx_normal};
+use serde::{Serialize,Deserialize};
+use rand::{Rng,RngCore};
+use serde_json::{Map,Value};
+
+use std::f32::consts::PI;
+
+/// Point of a surface being shaded
+pub struct SurfacePoint {
+    pub point: Vec3,
+    /// The point in the object's own space, where 3D textures are evaluated
+    pub object_point: Vec3,
+    /// Unit normal, facing the incoming ray
+    pub norm: Vec3,
+    /// Texture coordinates, for shapes that have them
+    pub uv: Option<(f32, f32)>,
+    /// Width of the area the ray sees around the point, in texture coordinates
+    pub footprint: f32,
+    /// Object space ∂p/∂u and ∂p/∂v, for shapes with texture coordinates
+    pub derivatives: Option<(Vec3, Vec3)>,
+    /// Color the shape gives the point, e.g. from an OBJ texture, replacing the material's own
+    pub color: Option<[f32; 3]>,
+}
+
+impl SurfacePoint {
+    /// Hit `intersection` of `object` at `point`, seen by a ray `ray_width` wide there
+    pub fn new(object: &Object, intersection: &IntersectionResult, point: Vec3, norm: Vec3, ray_width: f32) -> SurfacePoint {
+        let size = &object.size;
+        let scale = (size.x().abs() + size.y().abs() + size.z().abs()) / 3.0;
+        let mut surface = SurfacePoint {
+            object_point: intersection.point.clone()
+                .unwrap_or_else(|| &object.rotation * (&point - &object.position) / size),
+            point,
+            norm,
+            uv: intersection.uv,
+            footprint: ray_width / scale * intersection.uv_density,
+            derivatives: intersection.derivatives.clone(),
+            color: None,
+        };
+        surface.color = object.shape.texture().map(|texture| texture.color(&surface));
+        if let Some(relief) = object.material().relief() {
+            surface.norm = relief.normal(&surface, object);
+        }
+        surface
+    }
+
+    /// Texture lookup point moved by `offset` in object space, with texture coordinates `uv`
+    pub fn shifted(&self, offset: &Vec3, uv: Option<(f32, f32)>) -> SurfacePoint {
+        SurfacePoint {
+            point: self.point.clone(),
+            object_point: &self.object_point + offset,
+            norm: self.norm.clone(),
+            uv,
+            footprint: self.footprint,
+            derivatives: self.derivatives.clone(),
+            color: self.color,
+        }
+    }
+}
+
+/// How a surface scatters light.
+///
+/// `reflected` and `sample` describe the glossy and diffuse part of the surface. Perfect mirror
+/// reflection and refraction are blended in by the tracers with the `reflection` and
+/// `transparency` weights, like for the built-in Phong objects. Materials whose highlight
+/// mirrors the scene also give `ray_trace` the directions to trace it with `sample_glossy`.
+#[typetag::serde(tag="type")]
+pub trait Material: std::fmt::Debug + Sync + Send {
+    fn init(&mut self) {
+        // empty
+    }
+    /// Bumps tilting the shading normal, `None` for smooth surfaces
+    fn relief(&self) -> Option<&Relief> {
+        None
+    }
+    /// Share of the light arriving along the unit vector `to_light` that leaves toward the eye,
+    /// with `eye` the direction of the incoming ray. A white diffuse surface facing the light
+    /// reflects 1, so this is the BRDF times the cosine times pi.
+    fn reflected(&self, surface: &SurfacePoint, to_light: &Vec3, eye: &Vec3) -> [f32; 3];
+    /// Part of `reflected` that isn't traced with `sample_glossy`, for lights rays can hit
+    fn diffuse_reflected(&self, surface: &SurfacePoint, to_light: &Vec3, eye: &Vec3) -> [f32; 3] {
+        self.reflected(surface, to_light, eye)
+    }
+    /// Direction a path continues in after a diffuse or glossy bounce, with the factor its
+    /// throughput is multiplied by. `None` ends the path.
+    fn sample(&self, surface: &SurfacePoint, eye: &Vec3, rng: &mut dyn RngCore) -> Option<(Vec3, [f32; 3])>;
+    /// Whether `ray_trace` traces the reflection of the scene in the highlight with `sample_glossy`
+    fn traces_glossy(&self) -> bool {
+        false
+    }
+    /// Direction of a ray traced for the highlight, picked by `u` and `v` uniform in [0, 1),
+    /// with the factor the light coming back along it is multiplied by. `None` when the
+    /// direction leaves below the surface.
+    fn sample_glossy(&self, _surface: &SurfacePoint, _eye: &Vec3, _u: f32, _v: f32) -> Option<(Vec3, [f32; 3])> {
+        None
+    }
+    /// Overall color of the surface, used for ambient light and to tint transmitted light
+    fn albedo(&self, surface: &SurfacePoint) -> [f32; 3];
+    /// Weight of perfect mirror reflection at `surface`
+    fn reflection(&self, _surface: &SurfacePoint) -> f32 {
+        0.0
+    }
+    /// Roughness in [0, 1] spreading the mirror reflection, 0 for a perfect mirror
+    fn glossiness(&self, _surface: &SurfacePoint) -> f32 {
+        0.0
+    }
+    /// Reflected rays averaged by `ray_trace` for a rough mirror reflection or a traced highlight
+    fn glossy_samples(&self) -> u32 {
+        1
+    }
+    /// Weight of light passing through the surface at `surface`
+    fn transparency(&self, _surface: &SurfacePoint) -> f32 {
+        0.0
+    }
+    /// Refractive index of the inside of the surface
+    fn refractive(&self, _surface: &SurfacePoint) -> f32 {
+        1.0
+    }
+}
+
+/// Lambert diffuse plus a Phong highlight, the model objects use without a `material`
+#[derive(Serialize,Deserialize,Debug)]
+pub struct PhongMaterial {
+    #[serde(default = "ColorParam::white")]
+    color: ColorParam,
+    /// Phong exponent, 0 turns the highlight off
+    #[serde(default)]
+    specular: u32,
+    #[serde(default)]
+    reflection: ScalarParam,
+    /// Roughness of the mirror reflection, for brushed metal and satin finishes
+    #[serde(default)]
+    glossiness: ScalarParam,
+    #[serde(default = "Object::default_glossy_samples")]
+    glossy_samples: u32,
+    #[serde(default)]
+    transparency: ScalarParam,
+    #[serde(default = "Object::default_refractive")]
+    refractive: f32,
+    #[serde(flatten)]
+    relief: Relief,
+}
+
+impl PhongMaterial {
+    pub fn new(color: [f32; 3], specular: u32, reflection: f32, transparency: f32, refractive: f32) -> PhongMaterial {
+        PhongMaterial {
+            color: color.into(),
+            specular,
+            reflection: reflection.into(),
+            glossiness: ScalarParam::default(),
+            glossy_samples: Object::default_glossy_samples(),
+            transparency: transparency.into(),
+            refractive,
+            relief: Relief::default(),
+        }
+    }
+
+    pub fn set_glossiness(mut self, glossiness: f32, glossy_samples: u32) -> PhongMaterial {
+        self.glossiness = glossiness.into();
+        self.glossy_samples = glossy_samples;
+        self
+    }
+}
+
+/// Phong highlight seen from `eye` for light arriving along `to_light`
+fn phong(norm: &Vec3, to_light: &Vec3, eye: &Vec3, s: u32) -> f32 {
+    let r = norm * 2.0 * norm.dot(to_light) - to_light;
+    let eye_cos_r = -eye.cos(&r);
+    if eye_cos_r <= 0.0 {
+        return 0.0;
+    }
+    eye_cos_r.powf(s as f32)
+}
+
+#[typetag::serde(name="phong")]
+impl Material for PhongMaterial {
+    fn init(&mut self) {
+        self.color.init();
+        self.reflection.init();
+        self.glossiness.init();
+        self.transparency.init();
+        self.relief.init();
+    }
+
+    fn relief(&self) -> Option<&Relief> {
+        (!self.relief.is_flat()).then_some(&self.relief)
+    }
+
+    fn reflected(&self, surface: &SurfacePoint, to_light: &Vec3, eye: &Vec3) -> [f32; 3] {
+        let mut factor = f32::max(to_light.dot(&surface.norm), 0.0);
+        if self.specular > 0 {
+            factor += phong(&surface.norm, to_light, eye, self.specular);
+        }
+        self.albedo(surface).map(|c| c * factor)
+    }
+
+    fn sample(&self, surface: &SurfacePoint, _eye: &Vec3, mut rng: &mut dyn RngCore) -> Option<(Vec3, [f32; 3])> {
+        // The highlight is left to direct lighting
+        Some((cosine_hemisphere(&surface.norm, &mut rng), self.albedo(surface)))
+    }
+
+    fn albedo(&self, surface: &SurfacePoint) -> [f32; 3] {
+        surface.color.unwrap_or_else(|| self.color.color(surface))
+    }
+
+    fn reflection(&self, surface: &SurfacePoint) -> f32 {
+        self.reflection.value(surface)
+    }
+
+    fn glossiness(&self, surface: &SurfacePoint) -> f32 {
+        self.glossiness.value(surface)
+    }
+
+    fn glossy_samples(&self) -> u32 {
+        self.glossy_samples
+    }
+
+    fn transparency(&self, surface: &SurfacePoint) -> f32 {
+        self.transparency.value(surface)
+    }
+
+    fn refractive(&self, _surface: &SurfacePoint) -> f32 {
+        self.refractive
+    }
+}
+
+/// Metallic-roughness material with a GGX microfacet highlight, as used by glTF and game engines.
+///
+/// Dielectrics reflect 4% at normal incidence and diffuse the rest in `base_color`, metals
+/// reflect `base_color` and don't diffuse. `metallic` blends between the two.
+#[derive(Serialize,Deserialize,Debug)]
+pub struct GgxMaterial {
+    #[serde(default = "ColorParam::white")]
+    base_color: ColorParam,
+    #[serde(default)]
+    metallic: ScalarParam,
+    /// Perceptual roughness in [0, 1], squared into the GGX width
+    #[serde(default = "GgxMaterial::default_roughness")]
+    roughness: ScalarParam,
+    /// Weight of light passing through the surface
+    #[serde(default)]
+    transmission: ScalarParam,
+    #[serde(default = "GgxMaterial::default_ior")]
+    ior: ScalarParam,
+    /// Rays `ray_trace` follows for the reflection of the scene in the highlight
+    #[serde(default = "Object::default_glossy_samples")]
+    glossy_samples: u32,
+    #[serde(flatten)]
+    relief: Relief,
+}
+
+/// Parameters of a `GgxMaterial` at one surface point
+struct GgxLobes {
+    base_color: [f32; 3],
+    metallic: f32,
+    /// GGX width, kept off zero where the distribution becomes a spike
+    alpha: f32,
+}
+
+impl GgxLobes {
+    /// Chance of sampling the highlight rather than the diffuse lobe
+    fn specular_probability(&self) -> f32 {
+        0.25 + 0.75 * self.metallic
+    }
+
+    /// Normal distribution for the cosine between the normal and the half vector
+    fn distribution(&self, cos_h: f32) -> f32 {
+        let a2 = self.alpha * self.alpha;
+        let d = cos_h * cos_h * (a2 - 1.0) + 1.0;
+        a2 / (PI * d * d)
+    }
+
+    /// Height-correlated Smith masking-shadowing, divided by 4 cos_l cos_v
+    fn visibility(&self, cos_l: f32, cos_v: f32) -> f32 {
+        let a2 = self.alpha * self.alpha;
+        let l = cos_v * (cos_l * cos_l * (1.0 - a2) + a2).sqrt();
+        let v = cos_l * (cos_v * cos_v * (1.0 - a2) + a2).sqrt();
+        0.5 / (l + v)
+    }
+
+    /// Schlick reflectance of a channel with base color `c`, for the cosine between the half
+    /// vector and the eye
+    fn fresnel(&self, c: f32, cos_d: f32) -> f32 {
+        let f0 = 0.04 * (1.0 - self.metallic) + c * self.metallic;
+        f0 + (1.0 - f0) * (1.0 - cos_d.clamp(0.0, 1.0)).powi(5)
+    }
+
+    /// Diffuse and highlight parts of `Material::reflected`
+    fn reflected_parts(&self, norm: &Vec3, to_light: &Vec3, to_eye: &Vec3) -> ([f32; 3], [f32; 3]) {
+        let cos_l = to_light.dot(norm);
+        let cos_v = to_eye.dot(norm);
+        if cos_l <= 0.0 || cos_v <= 0.0 {
+            return ([0.0; 3], [0.0; 3]);
+        }
+        let half = (to_light + to_eye).norm();
+        let cos_h = half.dot(norm).max(0.0);
+        let cos_d = half.dot(to_eye);
+        let specular = self.distribution(cos_h) * self.visibility(cos_l, cos_v);
+
+        let diffuse = self.base_color.map(|c| (1.0 - self.fresnel(c, cos_d)) * (1.0 - self.metallic) * c * cos_l);
+        let glossy = self.base_color.map(|c| PI * self.fresnel(c, cos_d) * specular * cos_l);
+        (diffuse, glossy)
+    }
+
+    fn reflected(&self, norm: &Vec3, to_light: &Vec3, to_eye: &Vec3) -> [f32; 3] {
+        let (diffuse, glossy) = self.reflected_parts(norm, to_light, to_eye);
+        [0, 1, 2].map(|i| diffuse[i] + glossy[i])
+    }
+
+    /// Highlight ray for the half vector picked by `u` and `v` from the GGX distribution,
+    /// weighted by the highlight over the density of picking it
+    fn sample_glossy(&self, norm: &Vec3, to_eye: &Vec3, u: f32, v: f32) -> Option<(Vec3, [f32; 3])> {
+        let half = ggx_normal(norm, self.alpha, u, v);
+        let cos_d = half.dot(to_eye);
+        let to_light = &half * (2.0 * cos_d) - to_eye;
+        let cos_l = to_light.dot(norm);
+        let cos_v = to_eye.dot(norm);
+        let cos_h = half.dot(norm);
+        if cos_l <= 0.0 || cos_v <= 0.0 || cos_d <= 0.0 || cos_h <= 0.0 {
+            return None;
+        }
+        // The distribution cancels out of D G / (4 cos_l cos_v) cos_l over D cos_h / (4 cos_d)
+        let factor = self.visibility(cos_l, cos_v) * cos_l * 4.0 * cos_d / cos_h;
+        Some((to_light, self.base_color.map(|c| self.fresnel(c, cos_d) * factor)))
+    }
+
+    /// Density of `sample` picking `to_light`, per solid angle
+    fn density(&self, norm: &Vec3, to_light: &Vec3, to_eye: &Vec3) -> f32 {
+        let half = (to_light + to_eye).norm();
+        let cos_h = half.dot(norm).max(0.0);
+        let specular = self.distribution(cos_h) * cos_h / (4.0 * half.dot(to_eye).abs().max(1e-6));
+        let diffuse = to_light.dot(norm).max(0.0) / PI;
+        let p = self.specular_probability();
+        p * specular + (1.0 - p) * diffuse
+    }
+}
+
+impl GgxMaterial {
+    #[allow(dead_code)]
+    pub fn new(base_color: [f32; 3], metallic: f32, roughness: f32) -> GgxMaterial {
+        GgxMaterial {
+            base_color: base_color.into(),
+            metallic: metallic.into(),
+            roughness: roughness.into(),
+            transmission: ScalarParam::default(),
+            ior: GgxMaterial::default_ior(),
+            glossy_samples: Object::default_glossy_samples(),
+            relief: Relief::default(),
+        }
+    }
+
+    pub fn default_roughness() -> ScalarParam {
+        ScalarParam::Constant(0.5)
+    }
+
+    pub fn default_ior() -> ScalarParam {
+        ScalarParam::Constant(1.5)
+    }
+
+    fn lobes(&self, surface: &SurfacePoint) -> GgxLobes {
+        let roughness = self.roughness.value(surface).clamp(0.0, 1.0);
+        GgxLobes {
+            base_color: self.albedo(surface),
+            metallic: self.metallic.value(surface).clamp(0.0, 1.0),
+            alpha: (roughness * roughness).max(1e-3),
+        }
+    }
+}
+
+#[typetag::serde(name="ggx")]
+impl Material for GgxMaterial {
+    fn init(&mut self) {
+        self.base_color.init();
+        self.metallic.init();
+        self.roughness.init();
+        self.transmission.init();
+        self.ior.init();
+        self.relief.init();
+    }
+
+    fn relief(&self) -> Option<&Relief> {
+        (!self.relief.is_flat()).then_some(&self.relief)
+    }
+
+    fn reflected(&self, surface: &SurfacePoint, to_light: &Vec3, eye: &Vec3) -> [f32; 3] {
+        self.lobes(surface).reflected(&surface.norm, to_light, &-eye)
+    }
+
+    fn diffuse_reflected(&self, surface: &SurfacePoint, to_light: &Vec3, eye: &Vec3) -> [f32; 3] {
+        self.lobes(surface).reflected_parts(&surface.norm, to_light, &-eye).0
+    }
+
+    fn traces_glossy(&self) -> bool {
+        true
+    }
+
+    fn sample_glossy(&self, surface: &SurfacePoint, eye: &Vec3, u: f32, v: f32) -> Option<(Vec3, [f32; 3])> {
+        self.lobes(surface).sample_glossy(&surface.norm, &-eye, u, v)
+    }
+
+    fn glossy_samples(&self) -> u32 {
+        self.glossy_samples
+    }
+
+    fn sample(&self, surface: &SurfacePoint, eye: &Vec3, mut rng: &mut dyn RngCore) -> Option<(Vec3, [f32; 3])> {
+        let lobes = self.lobes(surface);
+        let norm = &surface.norm;
+        let to_eye = -eye;
+        let to_light = if rng.gen::<f32>() < lobes.specular_probability() {
+            // Half vector from the GGX distribution, mirrored into the light direction
+            let half = ggx_normal(norm, lobes.alpha, rng.gen(), rng.gen());
+            &half * (2.0 * half.dot(&to_eye)) - &to_eye
+        } else {
+            cosine_hemisphere(norm, &mut rng)
+        };
+        if to_light.dot(norm) <= 0.0 {
+            return None;
+        }
+        let density = lobes.density(norm, &to_light, &to_eye);
+        if density <= 0.0 {
+            return None;
+        }
+        let weight = lobes.reflected(norm, &to_light, &to_eye).map(|c| c / (PI * density));
+        Some((to_light, weight))
+    }
+
+    fn albedo(&self, surface: &SurfacePoint) -> [f32; 3] {
+        surface.color.unwrap_or_else(|| self.base_color.color(surface))
+    }
+
+    fn transparency(&self, surface: &SurfacePoint) -> f32 {
+        self.transmission.value(surface)
+    }
+
+    fn refractive(&self, surface: &SurfacePoint) -> f32 {
+        self.ior.value(surface)
+    }
+}
+
+/// Replaces material names in the objects of the scene file `config`, including the parts of
+/// CSG shapes, by the materials of its top-level `materials` map. Library entries and inline
+/// object materials may name a `base` material to inherit fields from and override some of them.
+pub fn resolve_library(config: &mut Value) -> Result<(), String> {
+    let library = match config.as_object_mut().and_then(|config| config.remove("materials")) {
+        Some(Value::Object(library)) => library,
+        Some(_) => return Err("materials must map names to materials".to_string()),
+        None => Map::new(),
+    };
+    let mut resolved = Map::new();
+    for name in library.keys() {
+        resolve_named(name, &library, &mut resolved, &mut vec![])?;
+    }
+
+    let Some(Value::Array(objects)) = config.get_mut("objects") else {
+        return Ok(());
+    };
+    for object in objects {
+        resolve_object(object, &library, &mut resolved)?;
+    }
+    Ok(())
+}
+
+/// Resolves the material of `object` and of the objects its shape is built from
+fn resolve_object(object: &mut Value, library: &Map<String, Value>, resolved: &mut Map<String, Value>) -> Result<(), String> {
+    if let Some(material) = object.get_mut("material") {
+        match material {
+            Value::String(name) => {
+                *material = resolved.get(name.as_str()).cloned()
+                    .ok_or_else(|| format!("Unknown material {}", name))?;
+            },
+            Value::Object(fields) if fields.contains_key("base") => {
+                *material = inherit(fields, library, resolved, &mut vec![])?;
+            },
+            _ => {},
+        }
+    }
+    // Parts of `difference` and `intersection` shapes are objects with their own material
+    if let Some(Value::Object(shape)) = object.get_mut("shape") {
+        for part in ["shape1", "shape2"] {
+            if let Some(part) = shape.get_mut(part) {
+                resolve_object(part, library, resolved)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Library material `name` with its base materials merged in, memoized in `resolved`.
+/// `chain` holds the materials being resolved, to catch cycles.
+fn resolve_named(name: &str, library: &Map<String, Value>, resolved: &mut Map<String, Value>,
+                 chain: &mut Vec<String>) -> Result<Value, String> {
+    if let Some(material) = resolved.get(name) {
+        return Ok(material.clone());
+    }
+    if chain.iter().any(|other| other == name) {
+        return Err(format!("Material {} inherits from itself", name));
+    }
+    let Some(Value::Object(fields)) = library.get(name) else {
+        return Err(format!("Unknown material {}", name));
+    };
+    chain.push(name.to_string());
+    let material = inherit(fields, library, resolved, chain)?;
+    chain.pop();
+    resolved.insert(name.to_string(), material.clone());
+    Ok(material)
+}
+
+/// `fields` laid over the material named by their `base` field, if any
+fn inherit(fields: &Map<String, Value>, library: &Map<String, Value>, resolved: &mut Map<String, Value>,
+           chain: &mut Vec<String>) -> Result<Value, String> {
+    let mut material = match fields.get("base") {
+        None => Map::new(),
+        Some(Value::String(base)) => match resolve_named(base, library, resolved, chain)? {
+            Value::Object(base) => base,
+            _ => unreachable!("library materials resolve to objects"),
+        },
+        Some(_) => return Err("base must be the name of a material".to_string()),
+    };
+    for (key, value) in fields {
+        if key != "base" {
+            material.insert(key.clone(), value.clone());
+        }
+    }
+    Ok(Value::Object(material))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn library_materials_inherit_and_override() {
+        let mut config = json!({
+            "materials": {
+                "gold": {"type": "ggx", "base_color": [1.0, 0.8, 0.3], "metallic": 1.0, "roughness": 0.2},
+                "brushed_gold": {"base": "gold", "roughness": 0.5},
+                "old_gold": {"base": "brushed_gold", "base_color": [0.6, 0.5, 0.3]}
+            },
+            "objects": [
+                {"material": "old_gold"},
+                {"material": {"base": "gold", "metallic": 0.5}},
+                {"material": {"type": "phong", "color": [1.0, 0.0, 0.0]}},
+                {}
+            ]
+        });
+        resolve_library(&mut config).unwrap();
+        assert!(config.get("materials").is_none());
+        assert_eq!(config["objects"], json!([
+            {"material": {"type": "ggx", "base_color": [0.6, 0.5, 0.3], "metallic": 1.0, "roughness": 0.5}},
+            {"material": {"type": "ggx", "base_color": [1.0, 0.8, 0.3], "metallic": 0.5, "roughness": 0.2}},
+            {"material": {"type": "phong", "color": [1.0, 0.0, 0.0]}},
+            {}
+        ]));
+    }
+
+    #[test]
+    fn library_materials_resolve_in_csg_parts() {
+        let mut config = json!({
+            "materials": {"red": {"type": "phong", "color": [1.0, 0.0, 0.0]}},
+            "objects": [{
+                "shape": {
+                    "type": "difference",
+                    "shape1": {"material": "red", "shape": {"type": "cube"}},
+                    "shape2": {"shape": {
+                        "type": "intersection",
+                        "shape1": {"material": {"base": "red", "specular": 10}, "shape": {"type": "sphere"}},
+                        "shape2": {"shape": {"type": "sphere"}}
+                    }}
+                }
+            }]
+        });
+        resolve_library(&mut config).unwrap();
+        let shape = &config["objects"][0]["shape"];
+        assert_eq!(shape["shape1"]["material"], json!({"type": "phong", "color": [1.0, 0.0, 0.0]}));
+        assert_eq!(shape["shape2"]["shape"]["shape1"]["material"],
+                   json!({"type": "phong", "color": [1.0, 0.0, 0.0], "specular": 10}));
+    }
+
+    #[test]
+    fn unknown_materials_are_errors() {
+        let mut config = json!({"objects": [{"material": "missing"}]});
+        assert_eq!(resolve_library(&mut config), Err("Unknown material missing".to_string()));
+
+        let mut config = json!({"materials": {"a": {"base": "missing"}}, "objects": []});
+        assert_eq!(resolve_library(&mut config), Err("Unknown material missing".to_string()));
+
+        let mut config = json!({"objects": [{"shape": {"type": "difference", "shape1": {"material": "missing"}}}]});
+        assert_eq!(resolve_library(&mut config), Err("Unknown material missing".to_string()));
+    }
+
+    #[test]
+    fn inheritance_cycles_are_errors() {
+        let mut config = json!({"materials": {"a": {"base": "a"}}, "objects": []});
+        assert_eq!(resolve_library(&mut config), Err("Material a inherits from itself".to_string()));
+
+        let mut config = json!({
+            "materials": {"a": {"base": "b"}, "b": {"base": "c"}, "c": {"base": "a"}},
+            "objects": []
+        });
+        assert!(resolve_library(&mut config).is_err_and(|error| error.ends_with("inherits from itself")));
+    }
+
+    #[test]
+    fn malformed_libraries_are_errors() {
+        let mut config = json!({"materials": [], "objects": []});
+        assert!(resolve_library(&mut config).is_err());
+
+        let mut config = json!({"materials": {"a": {"base": 1}}, "objects": []});
+        assert_eq!(resolve_library(&mut config), Err("base must be the name of a material".to_string()));
+    }
+}
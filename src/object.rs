@@ -1,11 +1,16 @@
-use crate::shapes::shape::{Shape,IntersectionResult,NoneShape};
+use crate::shapes::shape::{Shape,IntersectionResult,NoneShape,SurfaceSample};
 use crate::vec::Vec3;
 use crate::matrix::Matrix33;
+use crate::bvh::Aabb;
+use crate::material::{Material,PhongMaterial};
 use serde::{Serialize,Deserialize};
 
 
 #[derive(Serialize,Deserialize,Debug)]
 pub struct Object {
+    /// Lets other parts of the scene refer to the object
+    #[serde(default)]
+    pub name: Option<String>,
     #[serde(default = "Vec3::new_default")]
     pub position: Vec3,
     #[serde(default = "Vec3::new_unit")]
@@ -20,10 +25,34 @@ pub struct Object {
     pub specular: u32,
     #[serde(default)]
     pub reflection: f32,
-    //pub transparency: f32,
-    //pub refractive: f32,
+    /// Roughness of the mirror reflection, 0 for a perfect mirror
+    #[serde(default)]
+    pub glossiness: f32,
+    /// Reflected rays averaged per glossy reflection
+    #[serde(default = "Object::default_glossy_samples")]
+    pub glossy_samples: u32,
+    #[serde(default)]
+    pub transparency: f32,
+    #[serde(default = "Object::default_refractive")]
+    pub refractive: f32,
     #[serde(default = "Object::default_color")]
     pub color: [f32; 3],
+    /// How the surface scatters light, given inline or by name from the scene's `materials`.
+    /// Built from `color`, `specular`, `reflection`, `transparency` and `refractive` when missing
+    #[serde(default)]
+    pub material: Option<Box<dyn Material>>,
+    /// Linear RGB radiance the surface glows with, scaled by `emission_strength`
+    #[serde(default)]
+    pub emission: [f32; 3],
+    #[serde(default = "Object::default_emission_strength")]
+    pub emission_strength: f32,
+    /// Shadow rays toward the surface per shaded point, when it lights the scene
+    #[serde(default = "Object::default_emission_samples")]
+    pub emission_samples: u32,
+    /// Set by the scene when the emission is sampled like a light,
+    /// so paths hitting the surface after a diffuse bounce don't count it again
+    #[serde(skip)]
+    pub emission_sampled: bool,
     pub shape: Box<dyn Shape>,
 }
 
@@ -76,18 +105,67 @@ impl Object {
         self
     }
 
+    #[allow(dead_code)]
+    pub fn set_glossiness(mut self, glossiness: f32) -> Object {
+        self.glossiness = glossiness;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn set_transparency(mut self, transparency: f32) -> Object {
+        self.transparency = transparency;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn set_refractive(mut self, refractive: f32) -> Object {
+        self.refractive = refractive;
+        self
+    }
+
     #[allow(dead_code)]
     pub fn set_color(mut self, color: [f32; 3]) -> Object {
         self.color = color;
         self
     }
 
+    #[allow(dead_code)]
+    pub fn set_material(mut self, material: Box<dyn Material>) -> Object {
+        self.material = Some(material);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn set_emission(mut self, emission: [f32; 3], emission_strength: f32) -> Object {
+        self.emission = emission;
+        self.emission_strength = emission_strength;
+        self
+    }
+
     pub fn default_color() -> [f32; 3] {
         [1.0, 1.0, 1.0]
     }
 
+    pub fn default_emission_strength() -> f32 {
+        1.0
+    }
+
+    pub fn default_emission_samples() -> u32 {
+        16
+    }
+
+    pub fn default_glossy_samples() -> u32 {
+        8
+    }
+
+    pub fn default_refractive() -> f32 {
+        1.0
+    }
+
+    #[allow(dead_code)]
     pub fn new(shape: Box<dyn Shape>) -> Object {
         Object {
+            name: None,
             position: Vec3::new_default(),
             rotation_angle: [0, 0, 0],
             rotation: Matrix33::new_default(),
@@ -95,12 +173,22 @@ impl Object {
             size: Vec3::new(1.0, 1.0, 1.0),
             specular: 0,
             reflection: 0.0,
+            glossiness: 0.0,
+            glossy_samples: Object::default_glossy_samples(),
+            transparency: 0.0,
+            refractive: 1.0,
             color: [1.0, 1.0, 1.0],
+            material: None,
+            emission: [0.0, 0.0, 0.0],
+            emission_strength: 1.0,
+            emission_samples: Object::default_emission_samples(),
+            emission_sampled: false,
             shape
         }
     }
 
-    pub fn intersects(&self, start: &Vec3, ray: &Vec3) -> Option<(IntersectionResult, &Object)> {
+    /// Hit of the ray `start + t * ray`, see `Shape::intersects` for `t_min`
+    pub fn intersects(&self, start: &Vec3, ray: &Vec3, t_min: f32) -> Option<(IntersectionResult, &Object)> {
         //let r2 = ray.clone();
 
         let start = start - &self.position;
@@ -109,19 +197,80 @@ impl Object {
 
         let ray = &self.rotation * ray;
         let ray = ray / &self.size;
-        match self.shape.intersects(&start, &ray) {
+        match self.shape.intersects(&start, &ray, t_min) {
             None => None,
             Some(mut intersection) => {
                 intersection.norm = intersection.norm * &self.rotation;
+                intersection.max_norm = intersection.max_norm * &self.rotation;
                 Some((intersection,
                       self))
             }
         }
     }
 
+    /// Hit of a part of a CSG shape, with the hit point and texture mapping moved into
+    /// the space of the shape it is part of, like the normals
+    pub fn part_intersects(&self, start: &Vec3, ray: &Vec3, t_min: f32) -> Option<(IntersectionResult, &Object)> {
+        let (mut intersection, object) = self.intersects(start, ray, t_min)?;
+        intersection.point = intersection.point.map(|point| (point * &self.size) * &self.rotation + &self.position);
+        intersection.derivatives = intersection.derivatives.map(|(dpdu, dpdv)| {
+            ((dpdu * &self.size) * &self.rotation, (dpdv * &self.size) * &self.rotation)
+        });
+        intersection.uv_density = match &intersection.derivatives {
+            Some((dpdu, dpdv)) => 1.0 / dpdu.cross(dpdv).length().sqrt(),
+            None => {
+                let scale = (self.size.x().abs() + self.size.y().abs() + self.size.z().abs()) / 3.0;
+                intersection.uv_density / scale
+            },
+        };
+        Some((intersection, object))
+    }
+
+    /// Bounding box of the transformed shape, in the space `intersects` takes rays in
+    pub fn bounds(&self) -> Option<Aabb> {
+        let bounds = self.shape.bounds()?;
+        let corners = bounds.corners().map(|corner| (corner * &self.size) * &self.rotation + &self.position);
+        Some(Aabb::from_points(&corners))
+    }
+
+    pub fn material(&self) -> &dyn Material {
+        self.material.as_deref().expect("Object::init sets up the material")
+    }
+
+    /// Radiance leaving the surface on its own
+    pub fn emitted(&self) -> [f32; 3] {
+        self.emission.map(|c| c * self.emission_strength)
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        self.emitted().iter().any(|&c| c > 0.0)
+    }
+
+    /// Point on the surface in world space, its normal and the area it stands for,
+    /// for `u` and `v` uniform in [0, 1)
+    pub fn sample_surface(&self, u: f32, v: f32) -> Option<SurfaceSample> {
+        let sample = self.shape.sample_surface(u, v)?;
+        // Scaling stretches surface elements by the determinant and the change of their normal
+        let scaled_norm = &sample.norm / &self.size;
+        let area = sample.area * self.size.x() * self.size.y() * self.size.z() * scaled_norm.length();
+        Some(SurfaceSample {
+            point: (sample.point * &self.size) * &self.rotation + &self.position,
+            norm: (scaled_norm * &self.rotation).norm(),
+            area: area.abs(),
+        })
+    }
+
     pub fn init(&mut self) {
         self.rotation = Object::calc_rotation(self.rotation_angle[0] as f32, self.rotation_angle[1] as f32, self.rotation_angle[2] as f32);
         self.reverse_rotation = Object::calc_rotation(-self.rotation_angle[0] as f32, self.rotation_angle[1] as f32, self.rotation_angle[2] as f32);
+        if self.material.is_none() {
+            self.material = Some(Box::new(PhongMaterial::new(self.color, self.specular, self.reflection,
+                                                             self.transparency, self.refractive)
+                .set_glossiness(self.glossiness, self.glossy_samples)));
+        }
+        if let Some(material) = self.material.as_mut() {
+            material.init();
+        }
         self.shape.init()
     }
 }
@@ -129,6 +278,7 @@ impl Object {
 impl Default for Object {
     fn default() -> Object {
         Object {
+            name: None,
             position: Vec3::default(),
             size: Vec3::default(),
             rotation_angle: [0, 0, 0],
@@ -136,7 +286,16 @@ impl Default for Object {
             reverse_rotation: Matrix33::new_default(),
             specular: u32::default(),
             reflection: f32::default(),
+            glossiness: f32::default(),
+            glossy_samples: Object::default_glossy_samples(),
+            transparency: f32::default(),
+            refractive: Object::default_refractive(),
             color: <[f32; 3]>::default(),
+            material: None,
+            emission: <[f32; 3]>::default(),
+            emission_strength: Object::default_emission_strength(),
+            emission_samples: Object::default_emission_samples(),
+            emission_sampled: false,
             shape: Box::new(NoneShape::new()),
         }
     }
@@ -0,0 +1,26 @@
+use image::{ImageResult,Rgb32FImage,ImageFormat};
+use image::codecs::hdr::HdrEncoder;
+use crate::tonemap::ToneMap;
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Saves the framebuffer in the format given by the file extension.
+/// `.exr` and `.hdr` keep the linear float values, anything else is tone mapped to an 8-bit PNG.
+pub fn save(framebuffer: &Rgb32FImage, path: &str, tonemap: &ToneMap) -> ImageResult<()> {
+    let extension = Path::new(path).extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+    match extension.as_deref() {
+        Some("exr") => framebuffer.save_with_format(path, ImageFormat::OpenExr),
+        Some("hdr") => {
+            let writer = BufWriter::new(File::create(path)?);
+            HdrEncoder::new(writer).encode(&framebuffer.pixels().cloned().collect::<Vec<_>>(),
+                                           framebuffer.width() as usize,
+                                           framebuffer.height() as usize)
+        },
+        _ => tonemap.apply(framebuffer).save_with_format(path, ImageFormat::Png),
+    }
+}
+
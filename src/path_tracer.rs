@@ -0,0 +1,140 @@
+use crate::vec::Vec3;
+use crate::scene::Scene;
+use crate::lights::{Light,direct_light};
+use crate::material::SurfacePoint;
+use crate::sampling::glossy_reflection;
+use crate::{reflect_vec, fresnel};
+
+use rand::Rng;
+
+/// Unbiased Monte Carlo estimate of the radiance coming along the ray.
+///
+/// Every bounce picks one of the diffuse, mirror and transmission events of the hit object
+/// with the same weights `ray_trace` uses to blend them. Diffuse bounces gather direct light
+/// from lights that cast shadows and continue along a direction the material samples. Paths are cut
+/// by Russian roulette once `min_depth` bounces were traced.
+pub fn path_trace<R: Rng>(start: &Vec3, direction: &Vec3,
+                          objects: &Scene,
+                          lights: &Vec<Box<dyn Light>>,
+                          t_min: Option<f32>,
+                          min_depth: u16,
+                          rng: &mut R) -> [f32; 3] {
+    let eps = 1e-4;
+    let mut radiance = [0.0; 3];
+    let mut throughput = [1.0; 3];
+    let mut start = start.clone();
+    let mut direction = direction.norm();
+    let mut t_min = t_min;
+    let mut depth = 0;
+    // Emission hit after a diffuse bounce was already gathered as direct light
+    let mut specular_bounce = true;
+
+    loop {
+        let (intersection, object) = match objects.intersect(&start, &direction, t_min, None) {
+            Some(best_intersection) => best_intersection,
+            None => {
+                // The environment was gathered as direct light unless the path got here by a mirror
+                if specular_bounce {
+                    if let Some(background) = objects.background(&direction) {
+                        for i in 0..3 {
+                            radiance[i] += throughput[i] * background[i];
+                        }
+                    }
+                }
+                for light in lights {
+                    let background = light.background(&direction);
+                    for i in 0..3 {
+                        radiance[i] += throughput[i] * background[i];
+                    }
+                }
+                break;
+            }
+        };
+
+        if specular_bounce || !object.emission_sampled {
+            let emitted = object.emitted();
+            for i in 0..3 {
+                radiance[i] += throughput[i] * emitted[i];
+            }
+        }
+        let point = &start + &direction * intersection.distance;
+
+        let mut norm = intersection.norm.norm();
+        let inside = norm.dot(&direction) > 0.0;
+        if inside {
+            norm = -norm;
+        }
+        let over_point = &point + &norm * eps;
+        let under_point = &point - &norm * eps;
+
+        let material = object.material();
+        // Footprint of the last segment only, paths spread further after diffuse bounces
+        let ray_width = objects.pixel_spread() * intersection.distance;
+        let surface = SurfacePoint::new(object, &intersection, point, norm.clone(), ray_width);
+        // Bumps tilt the normal for shading, rays still leave from the geometric surface
+        let norm = surface.norm.clone();
+        let reflection = material.reflection(&surface);
+        let transparency = material.transparency(&surface);
+        let (reflectance, refracted) = if transparency > 0.0 {
+            if inside {
+                fresnel(&direction, &norm, material.refractive(&surface), 1.0)
+            } else {
+                fresnel(&direction, &norm, 1.0, material.refractive(&surface))
+            }
+        } else {
+            (0.0, None)
+        };
+
+        let local_weight = (1.0 - transparency) * (1.0 - reflection);
+        let reflect_weight = (1.0 - transparency) * reflection + transparency * reflectance;
+
+        let event: f32 = rng.gen();
+        if event < local_weight {
+            let direct = direct_light(lights, objects, &surface, &direction, material, false, rng);
+            for i in 0..3 {
+                radiance[i] += throughput[i] * direct[i];
+            }
+            let Some((bounced, weight)) = material.sample(&surface, &direction, rng) else {
+                break;
+            };
+            for i in 0..3 {
+                throughput[i] *= weight[i];
+            }
+            direction = bounced;
+            start = over_point;
+            specular_bounce = false;
+        } else if event < local_weight + reflect_weight {
+            let glossiness = material.glossiness(&surface);
+            direction = if glossiness > 0.0 {
+                glossy_reflection(&direction, &norm, glossiness, rng.gen(), rng.gen())
+            } else {
+                reflect_vec(&(-&direction), &norm).norm()
+            };
+            start = over_point;
+            specular_bounce = true;
+        } else if let Some(refracted) = refracted {
+            let base_color = material.albedo(&surface);
+            for i in 0..3 {
+                throughput[i] *= base_color[i];
+            }
+            direction = refracted;
+            start = under_point;
+            specular_bounce = true;
+        } else {
+            break;
+        }
+        t_min = Some(eps);
+
+        depth += 1;
+        if depth >= min_depth {
+            let survival = throughput.iter().cloned().fold(0.0, f32::max).clamp(0.05, 0.95);
+            if rng.gen::<f32>() > survival {
+                break;
+            }
+            for c in throughput.iter_mut() {
+                *c /= survival;
+            }
+        }
+    }
+    radiance
+}
@@ -0,0 +1,290 @@
+use crate::vec::Vec3;
+use crate::material::SurfacePoint;
+use crate::texture::{ColorParam,Texture};
+use serde::{Serialize,Deserialize};
+
+/// Pseudo-random bits for the integer lattice point `(x, y, z)`
+fn hash(x: i32, y: i32, z: i32) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6b343)
+        ^ (y as u32).wrapping_mul(0xd8163841)
+        ^ (z as u32).wrapping_mul(0xcb1ab31f);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846ca68b);
+    h ^ (h >> 16)
+}
+
+/// Pseudo-random value in [0, 1) for the lattice point, different for each `channel`
+fn random(x: i32, y: i32, z: i32, channel: u32) -> f32 {
+    (hash(x, y, z) ^ channel.wrapping_mul(0x9e3779b9)).wrapping_mul(0x85ebca6b) as f32 / 4_294_967_296.0
+}
+
+/// Dot product of the offset `(x, y, z)` with one of the 12 edge directions of a cube,
+/// picked by `hash`, as in improved Perlin noise
+fn gradient(hash: u32, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn mix(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// Perlin gradient noise, roughly in [-1, 1] and 0 at lattice points
+pub fn perlin(p: &Vec3) -> f32 {
+    let (fx, fy, fz) = (p.x().floor(), p.y().floor(), p.z().floor());
+    let (x, y, z) = (fx as i32, fy as i32, fz as i32);
+    let (dx, dy, dz) = (p.x() - fx, p.y() - fy, p.z() - fz);
+    let (u, v, w) = (fade(dx), fade(dy), fade(dz));
+
+    let corner = |i: i32, j: i32, k: i32| gradient(hash(x + i, y + j, z + k), dx - i as f32, dy - j as f32, dz - k as f32);
+    mix(mix(mix(corner(0, 0, 0), corner(1, 0, 0), u),
+            mix(corner(0, 1, 0), corner(1, 1, 0), u), v),
+        mix(mix(corner(0, 0, 1), corner(1, 0, 1), u),
+            mix(corner(0, 1, 1), corner(1, 1, 1), u), v), w)
+}
+
+/// Fractal Brownian motion: `octaves` layers of noise, each twice the frequency and half the
+/// amplitude of the last, normalized to about [-1, 1]. With `turbulence` the layers are
+/// folded by taking their absolute value, giving [0, 1] and sharp creases.
+pub fn fbm(p: &Vec3, octaves: u32, turbulence: bool) -> f32 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut total = 0.0;
+    let mut frequency = 1.0;
+    for _ in 0..octaves.max(1) {
+        let noise = perlin(&(p * frequency));
+        sum += amplitude * if turbulence { noise.abs() } else { noise };
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    sum / total
+}
+
+/// Distance from `p` to the closest of the points scattered one per lattice cell
+pub fn voronoi(p: &Vec3, jitter: f32) -> f32 {
+    let (x, y, z) = (p.x().floor() as i32, p.y().floor() as i32, p.z().floor() as i32);
+    let mut closest = f32::INFINITY;
+    for i in -1..=1 {
+        for j in -1..=1 {
+            for k in -1..=1 {
+                let (cx, cy, cz) = (x + i, y + j, z + k);
+                let feature = Vec3::new(cx as f32 + 0.5 + jitter * (random(cx, cy, cz, 0) - 0.5),
+                                        cy as f32 + 0.5 + jitter * (random(cx, cy, cz, 1) - 0.5),
+                                        cz as f32 + 0.5 + jitter * (random(cx, cy, cz, 2) - 0.5));
+                closest = closest.min((feature - p).length());
+            }
+        }
+    }
+    closest
+}
+
+fn blend(a: &[f32; 3], b: &[f32; 3], t: f32) -> [f32; 3] {
+    [0, 1, 2].map(|i| mix(a[i], b[i], t))
+}
+
+fn black() -> ColorParam {
+    ColorParam::Constant([0.0, 0.0, 0.0])
+}
+
+fn default_scale() -> f32 {
+    4.0
+}
+
+fn default_octaves() -> u32 {
+    4
+}
+
+/// Point of the pattern for the surface point, patterns are fixed to the object
+fn pattern_point(surface: &SurfacePoint, scale: f32) -> Vec3 {
+    &surface.object_point * scale
+}
+
+/// Cubes of alternating colors, `scale` cubes per object unit
+#[derive(Serialize,Deserialize,Debug)]
+pub struct CheckerTexture {
+    #[serde(default = "ColorParam::white")]
+    color1: ColorParam,
+    #[serde(default = "black")]
+    color2: ColorParam,
+    #[serde(default = "default_scale")]
+    scale: f32,
+}
+
+#[typetag::serde(name="checker")]
+impl Texture for CheckerTexture {
+    fn init(&mut self) {
+        self.color1.init();
+        self.color2.init();
+    }
+
+    fn color(&self, surface: &SurfacePoint) -> [f32; 3] {
+        let p = pattern_point(surface, self.scale);
+        // Nudged off cube faces so planes through the origin don't land on a boundary
+        let cell = (p.x() + 1e-4).floor() + (p.y() + 1e-4).floor() + (p.z() + 1e-4).floor();
+        if cell.rem_euclid(2.0) < 1.0 {
+            self.color1.color(surface)
+        } else {
+            self.color2.color(surface)
+        }
+    }
+}
+
+/// Smooth random blend of two colors, fBm of Perlin noise with `octaves` layers
+#[derive(Serialize,Deserialize,Debug)]
+pub struct NoiseTexture {
+    #[serde(default = "black")]
+    color1: ColorParam,
+    #[serde(default = "ColorParam::white")]
+    color2: ColorParam,
+    #[serde(default = "default_scale")]
+    scale: f32,
+    #[serde(default = "default_octaves")]
+    octaves: u32,
+    /// Folds the noise layers for billowy, cloud and fire like patterns
+    #[serde(default)]
+    turbulence: bool,
+}
+
+#[typetag::serde(name="noise")]
+impl Texture for NoiseTexture {
+    fn init(&mut self) {
+        self.color1.init();
+        self.color2.init();
+    }
+
+    fn color(&self, surface: &SurfacePoint) -> [f32; 3] {
+        let noise = fbm(&pattern_point(surface, self.scale), self.octaves, self.turbulence);
+        let t = if self.turbulence { noise } else { 0.5 + 0.5 * noise };
+        blend(&self.color1.color(surface), &self.color2.color(surface), t.clamp(0.0, 1.0))
+    }
+}
+
+/// Veins along the object x axis, bent by turbulence
+#[derive(Serialize,Deserialize,Debug)]
+pub struct MarbleTexture {
+    #[serde(default = "ColorParam::white")]
+    color1: ColorParam,
+    /// Color of the veins
+    #[serde(default = "black")]
+    color2: ColorParam,
+    #[serde(default = "default_scale")]
+    scale: f32,
+    #[serde(default = "default_octaves")]
+    octaves: u32,
+    /// How far the turbulence bends the veins
+    #[serde(default = "MarbleTexture::default_distortion")]
+    distortion: f32,
+}
+
+impl MarbleTexture {
+    pub fn default_distortion() -> f32 {
+        5.0
+    }
+}
+
+#[typetag::serde(name="marble")]
+impl Texture for MarbleTexture {
+    fn init(&mut self) {
+        self.color1.init();
+        self.color2.init();
+    }
+
+    fn color(&self, surface: &SurfacePoint) -> [f32; 3] {
+        let p = pattern_point(surface, self.scale);
+        let wave = (p.x() + self.distortion * fbm(&p, self.octaves, true)).sin();
+        // Thin dark veins rather than an even stripe pattern
+        let t = (1.0 - wave.abs()).powi(8);
+        blend(&self.color1.color(surface), &self.color2.color(surface), t)
+    }
+}
+
+/// Growth rings around the object y axis, `scale` rings per object unit
+#[derive(Serialize,Deserialize,Debug)]
+pub struct WoodTexture {
+    /// Color of the light wood between the rings
+    #[serde(default = "WoodTexture::default_color1")]
+    color1: ColorParam,
+    #[serde(default = "WoodTexture::default_color2")]
+    color2: ColorParam,
+    #[serde(default = "default_scale")]
+    scale: f32,
+    /// How much noise wobbles the rings
+    #[serde(default = "WoodTexture::default_distortion")]
+    distortion: f32,
+}
+
+impl WoodTexture {
+    pub fn default_color1() -> ColorParam {
+        ColorParam::Constant([0.65, 0.45, 0.25])
+    }
+
+    pub fn default_color2() -> ColorParam {
+        ColorParam::Constant([0.35, 0.2, 0.1])
+    }
+
+    pub fn default_distortion() -> f32 {
+        0.3
+    }
+}
+
+#[typetag::serde(name="wood")]
+impl Texture for WoodTexture {
+    fn init(&mut self) {
+        self.color1.init();
+        self.color2.init();
+    }
+
+    fn color(&self, surface: &SurfacePoint) -> [f32; 3] {
+        let p = pattern_point(surface, self.scale);
+        let radius = (p.x() * p.x() + p.z() * p.z()).sqrt();
+        // The grain is stretched along the trunk
+        let grain = Vec3::new(p.x(), p.y() * 0.1, p.z());
+        let rings = (radius + self.distortion * fbm(&grain, 2, false)).rem_euclid(1.0);
+        // Early wood fades into the darker late wood, then the next ring starts sharply
+        let t = rings.powi(3);
+        blend(&self.color1.color(surface), &self.color2.color(surface), t)
+    }
+}
+
+/// Cells around points scattered through space, `color1` at their centers and `color2`
+/// toward their borders, as for stones and scales
+#[derive(Serialize,Deserialize,Debug)]
+pub struct VoronoiTexture {
+    #[serde(default = "ColorParam::white")]
+    color1: ColorParam,
+    #[serde(default = "black")]
+    color2: ColorParam,
+    #[serde(default = "default_scale")]
+    scale: f32,
+    /// How far the cell points stray from a regular grid, 0 to 1
+    #[serde(default = "VoronoiTexture::default_jitter")]
+    jitter: f32,
+}
+
+impl VoronoiTexture {
+    pub fn default_jitter() -> f32 {
+        1.0
+    }
+}
+
+#[typetag::serde(name="voronoi")]
+impl Texture for VoronoiTexture {
+    fn init(&mut self) {
+        self.color1.init();
+        self.color2.init();
+    }
+
+    fn color(&self, surface: &SurfacePoint) -> [f32; 3] {
+        let distance = voronoi(&pattern_point(surface, self.scale), self.jitter.clamp(0.0, 1.0));
+        blend(&self.color1.color(surface), &self.color2.color(surface), distance.clamp(0.0, 1.0))
+    }
+}
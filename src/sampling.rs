@@ -0,0 +1,156 @@
+use crate::vec::Vec3;
+use rand::Rng;
+
+/// Builds two unit vectors which together with `norm` form an orthonormal basis
+pub fn orthonormal_basis(norm: &Vec3) -> (Vec3, Vec3) {
+    let helper = if norm.x().abs() > 0.9 {
+        Vec3::new(0.0, 1.0, 0.0)
+    } else {
+        Vec3::new(1.0, 0.0, 0.0)
+    };
+    let tangent = norm.cross(&helper).norm();
+    let bitangent = norm.cross(&tangent);
+    (tangent, bitangent)
+}
+
+/// Samples a direction in the hemisphere around `norm` with density cos(theta) / pi
+pub fn cosine_hemisphere<R: Rng>(norm: &Vec3, rng: &mut R) -> Vec3 {
+    let phi = 2.0 * std::f32::consts::PI * rng.gen::<f32>();
+    let r2: f32 = rng.gen();
+    let r = r2.sqrt();
+
+    let (tangent, bitangent) = orthonormal_basis(norm);
+    (tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) + norm * (1.0 - r2).sqrt()).norm()
+}
+
+/// Microfacet normal around `norm` from the GGX distribution of width `alpha`,
+/// with density D(h) cos(theta_h), for `u` and `v` uniform in [0, 1)
+pub fn ggx_normal(norm: &Vec3, alpha: f32, u: f32, v: f32) -> Vec3 {
+    let a2 = alpha * alpha;
+    let cos_h = ((1.0 - u) / (1.0 + (a2 - 1.0) * u)).sqrt();
+    let sin_h = (1.0 - cos_h * cos_h).max(0.0).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * v;
+    let (tangent, bitangent) = orthonormal_basis(norm);
+    (tangent * (sin_h * phi.cos()) + bitangent * (sin_h * phi.sin()) + norm * cos_h).norm()
+}
+
+/// Mirror reflection of the ray `direction` off a rough surface with unit normal `norm`,
+/// which faces the ray. `roughness` 0 gives the perfect mirror direction.
+pub fn glossy_reflection(direction: &Vec3, norm: &Vec3, roughness: f32, u: f32, v: f32) -> Vec3 {
+    let half = ggx_normal(norm, (roughness * roughness).max(1e-3), u, v);
+    let reflected = direction - &half * (2.0 * half.dot(direction));
+    // Bounces into the surface are folded back out rather than lost
+    let below = reflected.dot(norm);
+    if below < 0.0 {
+        (reflected - norm * (2.0 * below)).norm()
+    } else {
+        reflected.norm()
+    }
+}
+
+/// Maps a point of the unit square to the unit disk, keeping strata compact (Shirley–Chiu)
+pub fn concentric_disk(u: f32, v: f32) -> (f32, f32) {
+    let (a, b) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+    let quarter_pi = std::f32::consts::FRAC_PI_4;
+    let (r, phi) = if a.abs() > b.abs() {
+        (a, quarter_pi * (b / a))
+    } else {
+        (b, 2.0 * quarter_pi - quarter_pi * (a / b))
+    };
+    (r * phi.cos(), r * phi.sin())
+}
+
+/// `count` points in the unit square, one per cell of the largest square grid
+/// that fits, jittered inside their cells. Points left over are placed uniformly.
+pub fn stratified_square<R: Rng>(count: u32, rng: &mut R) -> Vec<(f32, f32)> {
+    let side = (count as f32).sqrt() as u32;
+    let mut points = Vec::with_capacity(count as usize);
+    for i in 0..side {
+        for j in 0..side {
+            points.push(((i as f32 + rng.gen::<f32>()) / side as f32,
+                         (j as f32 + rng.gen::<f32>()) / side as f32));
+        }
+    }
+    points.extend(uniform_square(count - side * side, rng));
+    points
+}
+
+/// `count` independent uniform points in the unit square
+pub fn uniform_square<R: Rng>(count: u32, rng: &mut R) -> Vec<(f32, f32)> {
+    (0..count).map(|_| (rng.gen(), rng.gen())).collect()
+}
+
+/// Piecewise constant distribution over `weights.len()` cells, for picking cells in proportion to their weight
+#[derive(Default)]
+pub struct Distribution {
+    /// Running total of the weights
+    sums: Vec<f32>,
+}
+
+impl Distribution {
+    pub fn new(weights: &[f32]) -> Distribution {
+        let sums = weights.iter().scan(0.0, |total, &weight| {
+            *total += weight;
+            Some(*total)
+        }).collect();
+        Distribution{sums}
+    }
+
+    pub fn total(&self) -> f32 {
+        self.sums.last().cloned().unwrap_or(0.0)
+    }
+
+    /// Probability of picking cell `index`
+    pub fn probability(&self, index: usize) -> f32 {
+        let previous = if index > 0 { self.sums[index - 1] } else { 0.0 };
+        (self.sums[index] - previous) / self.total()
+    }
+
+    /// Cell picked by `u` uniform in [0, 1), with the position of `u` inside the cell in [0, 1].
+    /// `None` when there is nothing to pick, without cells or with all weights 0.
+    pub fn sample(&self, u: f32) -> Option<(usize, f32)> {
+        if self.total() <= 0.0 {
+            return None;
+        }
+        let target = u * self.total();
+        let index = self.sums.partition_point(|&sum| sum <= target).min(self.sums.len() - 1);
+        let previous = if index > 0 { self.sums[index - 1] } else { 0.0 };
+        let offset = ((target - previous) / (self.sums[index] - previous)).clamp(0.0, 1.0);
+        Some((index, offset))
+    }
+}
+
+/// Uniformly distributed unit vector, with density 1 / (4 pi)
+pub fn uniform_sphere(u: f32, v: f32) -> Vec3 {
+    let z = 1.0 - 2.0 * u;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * v;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distribution_picks_cells_by_weight() {
+        let distribution = Distribution::new(&[1.0, 0.0, 3.0]);
+        assert_eq!(distribution.total(), 4.0);
+        assert_eq!(distribution.probability(2), 0.75);
+        assert_eq!(distribution.sample(0.0), Some((0, 0.0)));
+        assert_eq!(distribution.sample(0.125), Some((0, 0.5)));
+        // The empty cell is never picked
+        assert_eq!(distribution.sample(0.25), Some((2, 0.0)));
+        assert_eq!(distribution.sample(0.625), Some((2, 0.5)));
+    }
+
+    #[test]
+    fn distribution_without_weight_picks_nothing() {
+        assert_eq!(Distribution::new(&[]).sample(0.5), None);
+        assert_eq!(Distribution::new(&[0.0, 0.0]).sample(0.5), None);
+        assert_eq!(Distribution::default().sample(0.0), None);
+    }
+}
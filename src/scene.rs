@@ -0,0 +1,152 @@
+use crate::object::Object;
+use crate::shapes::shape::IntersectionResult;
+use crate::bvh::{Aabb,Bvh};
+use crate::vec::Vec3;
+use crate::environment::Environment;
+
+use serde::{Serialize,Deserialize};
+use derivative::Derivative;
+
+/// Top-level objects of the scene, with a bounding volume hierarchy over them
+#[derive(Serialize,Deserialize,Default,Derivative)]
+#[derivative(Debug)]
+#[serde(transparent)]
+pub struct Scene {
+    objects: Vec<Object>,
+
+    /// Objects in the BVH, in the order it was built from
+    #[serde(skip)]
+    #[derivative(Debug="ignore")]
+    bounded: Vec<usize>,
+    /// Objects without bounds, tested against every ray
+    #[serde(skip)]
+    #[derivative(Debug="ignore")]
+    unbounded: Vec<usize>,
+    /// Glowing objects whose surface can be sampled for direct lighting
+    #[serde(skip)]
+    #[derivative(Debug="ignore")]
+    emitters: Vec<usize>,
+    #[serde(skip)]
+    #[derivative(Debug="ignore")]
+    bvh: Bvh,
+    /// What rays leaving the scene see
+    #[serde(skip)]
+    #[derivative(Debug="ignore")]
+    environment: Option<Box<dyn Environment>>,
+    /// Angle between camera rays through neighbouring pixels, for filtering textures
+    #[serde(skip)]
+    #[derivative(Debug="ignore")]
+    pixel_spread: f32,
+}
+
+impl Scene {
+    pub fn init(&mut self) {
+        if let Some(environment) = self.environment.as_mut() {
+            environment.init();
+        }
+        for object in self.objects.iter_mut() {
+            object.init();
+        }
+
+        let mut bounds: Vec<Aabb> = vec![];
+        self.bounded.clear();
+        self.unbounded.clear();
+        for (index, object) in self.objects.iter().enumerate() {
+            match object.bounds() {
+                Some(object_bounds) => {
+                    bounds.push(object_bounds);
+                    self.bounded.push(index);
+                },
+                None => self.unbounded.push(index),
+            }
+        }
+        self.bvh = Bvh::new(&bounds, Bvh::DEFAULT_LEAF_SIZE);
+
+        self.emitters.clear();
+        for (index, object) in self.objects.iter_mut().enumerate() {
+            object.emission_sampled = object.is_emissive() && object.sample_surface(0.5, 0.5).is_some();
+            if object.emission_sampled {
+                self.emitters.push(index);
+            }
+        }
+    }
+
+    pub fn set_environment(&mut self, environment: Option<Box<dyn Environment>>) {
+        self.environment = environment;
+    }
+
+    pub fn set_pixel_spread(&mut self, pixel_spread: f32) {
+        self.pixel_spread = pixel_spread;
+    }
+
+    pub fn pixel_spread(&self) -> f32 {
+        self.pixel_spread
+    }
+
+    pub fn environment(&self) -> Option<&dyn Environment> {
+        self.environment.as_deref()
+    }
+
+    /// Radiance seen by a ray leaving the scene in the unit `direction`, `None` without an environment
+    pub fn background(&self, direction: &Vec3) -> Option<[f32; 3]> {
+        self.environment.as_ref().map(|environment| environment.radiance(direction))
+    }
+
+    pub fn emitters(&self) -> impl Iterator<Item = &Object> {
+        self.emitters.iter().map(|&index| &self.objects[index])
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Object> {
+        self.objects.iter().find(|object| object.name.as_deref() == Some(name))
+    }
+
+    fn intersect_object<'a>(object: &'a Object, start: &Vec3, direction: &Vec3,
+                            t_min: Option<f32>, t_max: Option<f32>) -> Option<(IntersectionResult, &'a Object)> {
+        match object.intersects(start, direction, t_min.unwrap_or(f32::NEG_INFINITY)) {
+            None => None,
+            Some((mut intersection, n_object)) => {
+                // A ray starting inside the object (e.g. a refracted one) hits the exit surface
+                if t_min.is_some() && intersection.distance < t_min.unwrap() {
+                    intersection.use_exit();
+                }
+                if (t_min.is_none() || intersection.distance >= t_min.unwrap())
+                    && (t_max.is_none() || intersection.distance <= t_max.unwrap()) {
+                    Some((intersection, n_object))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    pub fn intersect(&self, start: &Vec3, direction: &Vec3,
+                     t_min: Option<f32>, t_max: Option<f32>) -> Option<(IntersectionResult, &Object)> {
+        let mut best_result = self.bvh.closest_hit(
+            start, direction,
+            t_min.unwrap_or(f32::NEG_INFINITY), t_max.unwrap_or(f32::INFINITY),
+            |index| {
+                Scene::intersect_object(&self.objects[self.bounded[index]], start, direction, t_min, t_max)
+                    .map(|result| (result.0.distance, result))
+            }).map(|(_, result)| result);
+
+        for &index in &self.unbounded {
+            if let Some((intersection, object)) = Scene::intersect_object(&self.objects[index], start, direction, t_min, t_max) {
+                if best_result.is_none() || intersection.distance < best_result.as_ref().unwrap().0.distance {
+                    best_result = Some((intersection, object));
+                }
+            }
+        }
+        best_result
+    }
+
+    /// Checks whether anything blocks the ray from `point` along the unit `direction`
+    /// before `distance`, or anywhere along it when there is no end point
+    pub fn in_shadow(&self, point: &Vec3, direction: &Vec3, distance: Option<f32>) -> bool {
+        let t_min = 1e-4;
+        let blocks = |object: &Object| Scene::intersect_object(object, point, direction, Some(t_min), distance).is_some();
+
+        self.bvh.any_hit(point, direction, t_min, distance.unwrap_or(f32::INFINITY),
+                         |index| blocks(&self.objects[self.bounded[index]]))
+            || self.unbounded.iter().any(|&index| blocks(&self.objects[index]))
+    }
+}
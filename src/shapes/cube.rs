@@ -1,100 +1,124 @@
 use super::shape::Shape;
-use super::shape::IntersectionResult;
-use super::plane::Plane;
+use super::shape::{IntersectionResult,SurfaceSample,face_axes};
 use crate::vec::Vec3;
+use crate::bvh::Aabb;
 use serde::{Serialize,Deserialize};
 
 #[derive(Serialize,Deserialize,Debug)]
 pub struct Cube {
-    //point1: Vec3,
-    //point2: Vec3,
-
     #[serde(skip_serializing,skip_deserializing)]
-    planes: [Plane; 6],
+    min_point: Vec3,
+    #[serde(skip_serializing,skip_deserializing)]
+    max_point: Vec3,
 }
 
 #[typetag::serde(name="cube")]
 impl Shape for Cube {
     fn init(&mut self) {
-        let point1 = Vec3::new(-0.5, -0.5, -0.5);
-        let point2 = Vec3::new(0.5, 0.5, 0.5);
-
-        self.planes = [
-            Plane::new(Vec3::new(point1.x(), point2.y(), point2.z()),
-                       Vec3::new(0.0, 0.0, point1.z() - point2.z()),
-                       Vec3::new(0.0, point1.y() - point2.y(), 0.0)), // left
-            Plane::new(Vec3::new(point2.x(), point2.y(), point1.z()),
-                       Vec3::new(0.0, 0.0, point2.z() - point1.z()),
-                       Vec3::new(0.0, point1.y() - point2.y(), 0.0)), // right
-            Plane::new(Vec3::new(point2.x(), point2.y(), point1.z()),
-                       Vec3::new(0.0, point1.y() - point2.y(), 0.0),
-                       Vec3::new(point1.x() - point2.x(), 0.0, 0.0)), // front
-            Plane::new(Vec3::new(point2.x(), point2.y(), point2.z()),
-                       Vec3::new(point1.x() - point2.x(), 0.0, 0.0),
-                       Vec3::new(0.0, point1.y() - point2.y(), 0.0)), //back
-            Plane::new(Vec3::new(point2.x(), point1.y(), point2.z()),
-                       Vec3::new(point1.x() - point2.x(), 0.0, 0.0),
-                       Vec3::new(0.0, 0.0, point1.z() - point2.z())), // up
-            Plane::new(Vec3::new(point2.x(), point2.y(), point1.z()),
-                       Vec3::new(point1.x() - point2.x(), 0.0, 0.0),
-                       Vec3::new(0.0, 0.0, point2.z() - point1.z())), // down
-        ]
+        self.min_point = Vec3::new(-0.5, -0.5, -0.5);
+        self.max_point = Vec3::new(0.5, 0.5, 0.5);
     }
 
-    fn intersects(&self, start: &Vec3, ray: &Vec3) -> Option<IntersectionResult> {
-        let mut result: Option<IntersectionResult> = None;
-        let mut intersections_count = 0;
-        for plane in &self.planes {
-            if intersections_count == 2 {
-                break;
-            }
-            result = match plane.intersects(start, ray) {
-                None => result,
-                Some(mut intersection) => {
-                    intersections_count += 1;
-                    match result {
-                        None => Some(intersection),
-                        Some(mut best_intersection) => {
-                            if intersection.distance < best_intersection.distance {
-                                intersection.max_distance = best_intersection.distance;
-                                Some(intersection)
-                            } else {
-                                best_intersection.max_distance = intersection.distance;
-                                Some(best_intersection)
-                            }
-                        }
-                    }
+    fn intersects(&self, start: &Vec3, ray: &Vec3, _t_min: f32) -> Option<IntersectionResult> {
+        // Slab test: entry is the latest of the per-axis entries, exit is the earliest exit.
+        // The entry may be behind the start point when the ray begins inside the cube.
+        let start = [start.x(), start.y(), start.z()];
+        let ray = [ray.x(), ray.y(), ray.z()];
+        let min_point = [self.min_point.x(), self.min_point.y(), self.min_point.z()];
+        let max_point = [self.max_point.x(), self.max_point.y(), self.max_point.z()];
+
+        let mut t_near = f32::NEG_INFINITY;
+        let mut t_far = f32::INFINITY;
+        let mut near_axis = 0;
+        let mut far_axis = 0;
+        let mut near_sign = -1.0;
+        let mut far_sign = 1.0;
+
+        for axis in 0..3 {
+            if ray[axis] == 0.0 {
+                if start[axis] < min_point[axis] || start[axis] > max_point[axis] {
+                    return None;
                 }
+                continue;
+            }
+            let inv = 1.0 / ray[axis];
+            let t1 = (min_point[axis] - start[axis]) * inv;
+            let t2 = (max_point[axis] - start[axis]) * inv;
+            let (t_in, t_out, sign) = if t1 < t2 {
+                (t1, t2, -1.0)
+            } else {
+                (t2, t1, 1.0)
             };
+            if t_in > t_near {
+                t_near = t_in;
+                near_axis = axis;
+                near_sign = sign;
+            }
+            if t_out < t_far {
+                t_far = t_out;
+                far_axis = axis;
+                far_sign = -sign;
+            }
+        }
+
+        if t_near > t_far || t_far < 0.0 {
+            return None;
         }
 
-        return result;
+        let axis_norm = |axis: usize, sign: f32| -> Vec3 {
+            match axis {
+                0 => Vec3::new(sign, 0.0, 0.0),
+                1 => Vec3::new(0.0, sign, 0.0),
+                _ => Vec3::new(0.0, 0.0, sign),
+            }
+        };
+        // Each face shows the whole texture, see `face_axes` for its orientation
+        let norm = axis_norm(near_axis, near_sign);
+        let point = Vec3::new(start[0], start[1], start[2]) + Vec3::new(ray[0], ray[1], ray[2]) * t_near;
+        let center = (&self.min_point + &self.max_point) / 2.0;
+        let extent = &self.max_point - &self.min_point;
+        let (u_axis, v_axis) = face_axes(&norm);
+        let (u_size, v_size) = (u_axis.dot(&extent).abs(), v_axis.dot(&extent).abs());
+        let offset = &point - &center;
+        let uv = (offset.dot(&u_axis) / u_size + 0.5, offset.dot(&v_axis) / v_size + 0.5);
+        Some(IntersectionResult::new(t_near, t_far, norm)
+                 .set_max_norm(axis_norm(far_axis, far_sign))
+                 .set_mapping(point, uv, u_axis * u_size, v_axis * v_size))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.min_point.clone(), self.max_point.clone()))
+    }
+
+    fn sample_surface(&self, u: f32, v: f32) -> Option<SurfaceSample> {
+        // `u` picks one of the six unit faces and is then reused inside it
+        let face = ((u * 6.0) as usize).min(5);
+        let u = u * 6.0 - face as f32;
+        let axis = face % 3;
+        let sign = if face < 3 { -1.0 } else { 1.0 };
+
+        let mut point = [u - 0.5, v - 0.5, 0.0];
+        point.rotate_right(axis + 1);
+        point[axis] = 0.5 * sign;
+        let mut norm = [0.0; 3];
+        norm[axis] = sign;
+        Some(SurfaceSample {
+            point: Vec3::new(point[0], point[1], point[2]),
+            norm: Vec3::new(norm[0], norm[1], norm[2]),
+            area: 6.0,
+        })
     }
 }
 
 impl Cube {
     pub fn new(point1: &Vec3, point2: &Vec3) -> Cube {
         Cube {
-            planes: [
-                Plane::new(Vec3::new(point1.x(), point2.y(), point2.z()),
-                           Vec3::new(0.0, 0.0, point1.z() - point2.z()),
-                           Vec3::new(0.0, point1.y() - point2.y(), 0.0)), // left
-                Plane::new(Vec3::new(point2.x(), point2.y(), point1.z()),
-                           Vec3::new(0.0, 0.0, point2.z() - point1.z()),
-                           Vec3::new(0.0, point1.y() - point2.y(), 0.0)), // right
-                Plane::new(Vec3::new(point2.x(), point2.y(), point1.z()),
-                           Vec3::new(0.0, point1.y() - point2.y(), 0.0),
-                           Vec3::new(point1.x() - point2.x(), 0.0, 0.0)), // front
-                Plane::new(Vec3::new(point2.x(), point2.y(), point2.z()),
-                           Vec3::new(point1.x() - point2.x(), 0.0, 0.0),
-                           Vec3::new(0.0, point1.y() - point2.y(), 0.0)), //back
-                Plane::new(Vec3::new(point2.x(), point1.y(), point2.z()),
-                           Vec3::new(point1.x() - point2.x(), 0.0, 0.0),
-                           Vec3::new(0.0, 0.0, point1.z() - point2.z())), // up
-                Plane::new(Vec3::new(point2.x(), point2.y(), point1.z()),
-                           Vec3::new(point1.x() - point2.x(), 0.0, 0.0),
-                           Vec3::new(0.0, 0.0, point2.z() - point1.z())), // down
-            ]
+            min_point: Vec3::new(f32::min(point1.x(), point2.x()),
+                                 f32::min(point1.y(), point2.y()),
+                                 f32::min(point1.z(), point2.z())),
+            max_point: Vec3::new(f32::max(point1.x(), point2.x()),
+                                 f32::max(point1.y(), point2.y()),
+                                 f32::max(point1.z(), point2.z())),
         }
     }
 }
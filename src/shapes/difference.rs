@@ -1,6 +1,7 @@
 use super::shape::{Shape,IntersectionResult};
 use crate::object::Object;
 use crate::vec::Vec3;
+use crate::bvh::Aabb;
 use serde::{Serialize,Deserialize};
 
 #[derive(Serialize,Deserialize,Debug)]
@@ -11,26 +12,30 @@ pub struct Difference {
 
 #[typetag::serde(name="difference")]
 impl Shape for Difference {
-    fn intersects(&self, start: &Vec3, ray: &Vec3) -> Option<IntersectionResult> {
-        match self.shape1.intersects(start, ray) {
+    fn intersects(&self, start: &Vec3, ray: &Vec3, t_min: f32) -> Option<IntersectionResult> {
+        match self.shape1.part_intersects(start, ray, t_min) {
             None => None,
             Some((intersection1, _object1)) => {
-                match self.shape2.intersects(start, ray) {
+                match self.shape2.part_intersects(start, ray, t_min) {
                     None => Some(intersection1),
                     Some((intersection2, _object2)) => {
-                        if intersection1.distance < intersection2.distance {
-                            Some(IntersectionResult::new(intersection1.distance,
-                                                         f32::min(intersection1.max_distance,
-                                                                  intersection2.distance),
-                                                         intersection1.norm))
+                        // Surfaces carved out by shape2 face the opposite way of shape2's own normals.
+                        // What's left is a span in front of shape2 and one behind it, the first one
+                        // ending at t_min or later is hit
+                        if intersection2.max_distance < intersection1.distance
+                            || intersection2.distance > intersection1.max_distance {
+                            Some(intersection1)
+                        } else if intersection1.distance < intersection2.distance && intersection2.distance >= t_min {
+                            // Entering shape1 keeps its surface mapping
+                            Some(IntersectionResult{max_distance: intersection2.distance, ..intersection1}
+                                 .set_max_norm(-intersection2.norm))
+                        } else if intersection2.max_distance < intersection1.max_distance {
+                            Some(IntersectionResult::new(intersection2.max_distance,
+                                                         intersection1.max_distance,
+                                                         -intersection2.max_norm)
+                                 .set_max_norm(intersection1.max_norm))
                         } else {
-                            if intersection2.max_distance < intersection1.distance {
-                                Some(intersection1)
-                            } else {
-                                Some(IntersectionResult::new(intersection2.max_distance,
-                                                             intersection1.max_distance,
-                                                             intersection2.norm))
-                            }
+                            None
                         }
                     }
                 }
@@ -42,6 +47,10 @@ impl Shape for Difference {
         self.shape1.init();
         self.shape2.init();
     }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.shape1.bounds()
+    }
 }
 
 impl Difference {
@@ -53,3 +62,38 @@ impl Difference {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::sphere::Sphere;
+
+    #[test]
+    fn ray_from_inside_carved_shape_hits_its_far_wall() {
+        let mut hollow = Difference::new(Object::new(Box::new(Sphere::new())),
+                                         Object::new(Box::new(Sphere::new())).set_size(Vec3::new(0.5, 0.5, 0.5)));
+        hollow.init();
+        let hit = hollow.intersects(&Vec3::new(0.0, 0.0, 0.0), &Vec3::new(0.0, 0.0, 1.0), 1e-4).unwrap();
+        assert!((hit.distance - 0.25).abs() < 1e-5);
+        assert!((hit.max_distance - 0.5).abs() < 1e-5);
+        assert!(hit.norm.z() < -0.99);
+        assert!(hit.max_norm.z() > 0.99);
+    }
+
+    #[test]
+    fn part_mapping_is_in_the_space_of_the_difference() {
+        let mut shape = Difference::new(Object::new(Box::new(Sphere::new()))
+                                            .set_size(Vec3::new(2.0, 2.0, 2.0))
+                                            .set_position(Vec3::new(0.0, 0.0, 1.0)),
+                                        Object::new(Box::new(Sphere::new()))
+                                            .set_position(Vec3::new(0.0, 0.0, 5.0)));
+        shape.init();
+        let hit = shape.intersects(&Vec3::new(0.0, 0.0, -5.0), &Vec3::new(0.0, 0.0, 1.0), f32::NEG_INFINITY).unwrap();
+        let part = Sphere::new().intersects(&Vec3::new(0.0, 0.0, -5.0), &Vec3::new(0.0, 0.0, 1.0), f32::NEG_INFINITY).unwrap();
+        assert!((hit.distance - 5.0).abs() < 1e-5);
+        assert!(hit.point.unwrap().length() < 1e-5);
+        assert!((hit.uv_density - part.uv_density / 2.0).abs() < 1e-5);
+        let (dpdu, _) = hit.derivatives.unwrap();
+        assert!((dpdu.length() - 2.0 * part.derivatives.unwrap().0.length()).abs() < 1e-4);
+    }
+}
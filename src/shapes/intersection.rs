@@ -1,6 +1,7 @@
 use super::shape::{Shape,IntersectionResult};
 use crate::object::Object;
 use crate::vec::Vec3;
+use crate::bvh::Aabb;
 use serde::{Serialize,Deserialize};
 
 #[derive(Serialize,Deserialize,Debug)]
@@ -11,29 +12,28 @@ pub struct Intersection {
 
 #[typetag::serde(name="intersection")]
 impl Shape for Intersection {
-    fn intersects(&self, start: &Vec3, ray: &Vec3) -> Option<IntersectionResult> {
-        match self.shape1.intersects(start, ray) {
+    fn intersects(&self, start: &Vec3, ray: &Vec3, t_min: f32) -> Option<IntersectionResult> {
+        match self.shape1.part_intersects(start, ray, t_min) {
             None => None,
             Some((intersection1, _object1)) => {
-                match self.shape2.intersects(start, ray) {
+                match self.shape2.part_intersects(start, ray, t_min) {
                     None => None,
                     Some((intersection2, _object2)) => {
-                        if intersection1.distance < intersection2.distance {
-                            if intersection1.max_distance < intersection2.distance {
-                                None
-                            } else {
-                                Some(IntersectionResult::new(intersection2.distance,
-                                                             intersection1.max_distance,
-                                                             intersection2.norm))
-                            }
+                        let (max_distance, max_norm) = if intersection1.max_distance < intersection2.max_distance {
+                            (intersection1.max_distance, intersection1.max_norm.clone())
                         } else {
-                            if intersection2.max_distance < intersection1.distance {
-                                None
-                            } else {
-                                Some(IntersectionResult::new(intersection1.distance,
-                                                             intersection2.max_distance,
-                                                             intersection1.norm))
-                            }
+                            (intersection2.max_distance, intersection2.max_norm.clone())
+                        };
+                        // The entry, with its surface mapping, is on the shape entered last
+                        let entry = if intersection1.distance > intersection2.distance {
+                            intersection1
+                        } else {
+                            intersection2
+                        };
+                        if entry.distance > max_distance {
+                            None
+                        } else {
+                            Some(IntersectionResult{max_distance, ..entry}.set_max_norm(max_norm))
                         }
                     }
                 }
@@ -45,6 +45,13 @@ impl Shape for Intersection {
         self.shape1.init();
         self.shape2.init();
     }
+
+    fn bounds(&self) -> Option<Aabb> {
+        match (self.shape1.bounds(), self.shape2.bounds()) {
+            (Some(bounds1), Some(bounds2)) => Some(bounds1.intersection(&bounds2)),
+            (bounds1, bounds2) => bounds1.or(bounds2),
+        }
+    }
 }
 
 impl Intersection {
@@ -1,16 +1,16 @@
 use super::shape::Shape;
-use super::cube::Cube;
-use super::shape::IntersectionResult;
-use crate::object::Object;
+use super::shape::{IntersectionResult,SurfaceSample};
 use crate::vec::Vec3;
+use crate::bvh::{Aabb,Bvh};
+use crate::sampling::Distribution;
+
+use crate::texture::{ColorSpace,ImageTexture,Sampler,Texture};
 
 use tobj;
-use image::io::Reader as ImageReader;
-use image::Rgb32FImage;
 use serde::{Serialize,Deserialize};
 use derivative::Derivative;
-use std::cmp::{max,min};
-use std::sync::Arc;
+
+use std::collections::HashMap;
 
 
 #[derive(Debug,Clone)]
@@ -23,401 +23,186 @@ struct Triangle {
     pub tex_v0: Vec3,
     pub tex_v1: Vec3,
     pub tex_v2: Vec3,
+    /// Unit normals at the corners for smooth shading, `None` for a flat triangle
+    pub vertex_norms: Option<[Vec3; 3]>,
+    /// ∂p/∂u and ∂p/∂v, `None` where the texture coordinates don't span the triangle
+    pub derivatives: Option<(Vec3, Vec3)>,
 }
 
 impl Triangle {
     pub fn new(v0: &Vec3, v1: &Vec3, v2: &Vec3,
-               tex_v0: Vec3, tex_v1: Vec3, tex_v2: Vec3) -> Triangle {
+               tex_v0: Vec3, tex_v1: Vec3, tex_v2: Vec3,
+               vertex_norms: Option<[Vec3; 3]>) -> Triangle {
+        let (edge1, edge2) = (v1 - v0, v2 - v0);
+        let (du1, dv1) = (tex_v1.x() - tex_v0.x(), tex_v1.y() - tex_v0.y());
+        let (du2, dv2) = (tex_v2.x() - tex_v0.x(), tex_v2.y() - tex_v0.y());
+        let det = du1 * dv2 - du2 * dv1;
+        let derivatives = if det.abs() > 1e-12 {
+            Some(((&edge1 * dv2 - &edge2 * dv1) / det, (&edge2 * du1 - &edge1 * du2) / det))
+        } else {
+            None
+        };
+
         Triangle {
             edge1: v1 - v0,
             edge2: v2 - v0,
             v0: v0.clone(),
             norm: (v1 - v0).cross(&(v2 - v0)),
-            tex_v0: tex_v0,
-            tex_v1: tex_v1,
-            tex_v2: tex_v2,
+            tex_v0,
+            tex_v1,
+            tex_v2,
+            vertex_norms: vertex_norms.filter(|norms| norms.iter().all(|norm| norm.length() > 0.0))
+                .map(|norms| norms.map(|norm| norm.norm())),
+            derivatives,
         }
     }
 
-    pub fn min_x(&self) -> f32 {
-        self.v0.x().min((&self.edge1 + &self.v0).x()).min((&self.edge2 + &self.v0).x())
-    }
-
-    pub fn max_x(&self) -> f32 {
-        self.v0.x().max((&self.edge1 + &self.v0).x()).max((&self.edge2 + &self.v0).x())
+    pub fn bounds(&self) -> Aabb {
+        let v1 = &self.v0 + &self.edge1;
+        let v2 = &self.v0 + &self.edge2;
+        Aabb::from_points([&self.v0, &v1, &v2])
     }
-
-    pub fn min_y(&self) -> f32 {
-        self.v0.y().min((&self.edge1 + &self.v0).y()).min((&self.edge2 + &self.v0).y())
-    }
-
-    pub fn max_y(&self) -> f32 {
-        self.v0.y().max((&self.edge1 + &self.v0).y()).max((&self.edge2 + &self.v0).y())
-    }
-
-    pub fn min_z(&self) -> f32 {
-        self.v0.z().min((&self.edge1 + &self.v0).z()).min((&self.edge2 + &self.v0).z())
-    }
-
-    pub fn max_z(&self) -> f32 {
-        self.v0.z().max((&self.edge1 + &self.v0).z()).max((&self.edge2 + &self.v0).z())
-    }
-}
-
-#[derive(Debug,Serialize,Deserialize)]
-enum Split {
-    Leaf {
-        #[serde(skip_serializing,skip_deserializing)]
-        triangles: Vec<Triangle>,
-        #[serde(skip_serializing,skip_deserializing)]
-        texture: Option<Arc<Rgb32FImage>>,
-    },
-    Node {
-            left: Box<Split>,
-            left_box: Cube, 
-            right: Box<Split>,
-            right_box: Cube, 
-         }
 }
 
-impl Default for Split {
-    fn default() -> Self {
-        Split::Leaf{triangles: vec![], texture: None}
-    }
-}
-
-impl Split {
-    fn new_int(triangles: Vec<Triangle>, texture: Arc<Rgb32FImage>, skip_x: bool, skip_y: bool, skip_z: bool) -> Split {
-        // TODO: const
-        let leaf_limit = 256;
-        if triangles.len() < leaf_limit {
-            Split::Leaf{triangles: triangles, texture: Some(texture)}
-        } else {
-            let mut max_x: f32 = triangles[0].max_x();
-            let mut max_y: f32 = triangles[0].max_y();
-            let mut max_z: f32 = triangles[0].max_z();
-
-            let mut min_x: f32 = triangles[0].min_x();
-            let mut min_y: f32 = triangles[0].min_y();
-            let mut min_z: f32 = triangles[0].min_z();
-
-            for triangle in &triangles {
-                max_x = max_x.max(triangle.max_x());
-                max_y = max_z.max(triangle.max_y());
-                max_z = max_y.max(triangle.max_z());
-
-                min_x = min_x.min(triangle.min_x());
-                min_y = min_y.min(triangle.min_y());
-                min_z = min_z.min(triangle.min_z());
-            }
-
-            let dist_x = max_x - min_x;
-            let dist_y = max_y - min_y;
-            let dist_z = max_z - min_z;
-
-            let triangles_len = triangles.len();
-
-            if !skip_x && dist_x >= dist_y && dist_x >= dist_z {
-                let mean_x = min_x + dist_x / 2.0;
-                let left_cube = Cube::new(&Vec3::new(min_x, min_y, min_z), &Vec3::new(mean_x, max_y, max_z));
-                let right_cube = Cube::new(&Vec3::new(mean_x, min_y, min_z), &Vec3::new(max_x, max_y, max_z));
-                let mut left_triangles: Vec<Triangle> = vec![];
-                let mut right_triangles: Vec<Triangle> = vec![];
-                for triangle in triangles {
-                    if triangle.max_x() < mean_x {
-                        left_triangles.push(triangle);
-                    } else if triangle.min_x() > mean_x {
-                        right_triangles.push(triangle);
-                    } else {
-                        left_triangles.push(triangle.clone());
-                        right_triangles.push(triangle);
-                    }
-                }
-                //println!("{} {} {}", min_x, max_x, mean_x);
-                //println!("{} {} x", left_triangles.len(), right_triangles.len());
-                if left_triangles.len() == triangles_len {
-                    Split::new_int(left_triangles, texture, true, skip_y, skip_z)
-                } else if right_triangles.len() == triangles_len {
-                    Split::new_int(right_triangles, texture, true, skip_y, skip_z)
-                } else {
-                    Split::Node{left: Box::new(Split::new(left_triangles, texture.clone())),
-                                left_box: left_cube,
-                                right: Box::new(Split::new(right_triangles, texture)),
-                                right_box: right_cube}
-                }
-            } else if !skip_y && dist_y >= dist_x && dist_y >= dist_z {
-                let mean_y = min_y + dist_y / 2.0;
-                let left_cube = Cube::new(&Vec3::new(min_x, min_y, min_z), &Vec3::new(max_x, mean_y, max_z));
-                let right_cube = Cube::new(&Vec3::new(min_x, mean_y, min_z), &Vec3::new(max_x, max_y, max_z));
-                let mut left_triangles: Vec<Triangle> = vec![];
-                let mut right_triangles: Vec<Triangle> = vec![];
-                for triangle in triangles {
-                    if triangle.max_y() < mean_y {
-                        left_triangles.push(triangle);
-                    } else if triangle.min_y() > mean_y {
-                        right_triangles.push(triangle);
-                    } else {
-                        left_triangles.push(triangle.clone());
-                        right_triangles.push(triangle);
-                    }
-                }
-                //println!("{} {} y", left_triangles.len(), right_triangles.len());
-                if left_triangles.len() == triangles_len {
-                    Split::new_int(left_triangles, texture, skip_x, true, skip_z)
-                } else if right_triangles.len() == triangles_len {
-                    Split::new_int(right_triangles, texture, skip_x, true, skip_z)
-                } else {
-                    Split::Node{left: Box::new(Split::new(left_triangles, texture.clone())),
-                                left_box: left_cube,
-                                right: Box::new(Split::new(right_triangles, texture)),
-                                right_box: right_cube}
-                }
-            } else if !skip_z {
-                let mean_z = min_z + dist_z / 2.0;
-                let left_cube = Cube::new(&Vec3::new(min_x, min_y, min_z), &Vec3::new(max_x, max_y, mean_z));
-                let right_cube = Cube::new(&Vec3::new(min_x, min_y, mean_z), &Vec3::new(max_x, max_y, max_z));
-                let mut left_triangles: Vec<Triangle> = vec![];
-                let mut right_triangles: Vec<Triangle> = vec![];
-                //println!("{}", triangles.len());
-                for triangle in triangles {
-                    if triangle.max_z() < mean_z {
-                        left_triangles.push(triangle);
-                    } else if triangle.min_z() > mean_z {
-                        right_triangles.push(triangle);
-                    } else {
-                        left_triangles.push(triangle.clone());
-                        right_triangles.push(triangle);
-                    }
-                }
-                //println!("{} {} {}", min_z, max_z, mean_z);
-                //println!("{} {} z", left_triangles.len(), right_triangles.len());
-                if left_triangles.len() == triangles_len {
-                    Split::new_int(left_triangles, texture, skip_x, skip_y, true)
-                } else if right_triangles.len() == triangles_len {
-                    Split::new_int(right_triangles, texture, skip_x, skip_y, true)
-                } else {
-                    Split::Node{left: Box::new(Split::new(left_triangles, texture.clone())),
-                                left_box: left_cube,
-                                right: Box::new(Split::new(right_triangles, texture)),
-                                right_box: right_cube}
-                }
-            } else {
-                Split::Leaf{triangles: triangles, texture: Some(texture)}
-            }
+/// Vertex normals for triangles given by their corners, as the sum of the normals of the faces
+/// around each corner weighted by their angle there. Faces whose normals differ by more than
+/// `crease_angle` degrees from the corner's own face are left out, keeping that edge sharp.
+/// Corners are matched by position, so seams in the texture coordinates stay smooth.
+fn smooth_normals(triangles: &[[Vec3; 6]], crease_angle: f32) -> Vec<[Vec3; 3]> {
+    let key = |point: &Vec3| [point.x().to_bits(), point.y().to_bits(), point.z().to_bits()];
+    let face_norms: Vec<Vec3> = triangles.iter().map(|v| {
+        let norm = (&v[1] - &v[0]).cross(&(&v[2] - &v[0]));
+        if norm.length() > 0.0 { norm.norm() } else { norm }
+    }).collect();
+
+    // Faces around each position with their angle at it
+    let mut corners: HashMap<[u32; 3], Vec<(usize, f32)>> = HashMap::new();
+    for (index, v) in triangles.iter().enumerate() {
+        for corner in 0..3 {
+            let edge1 = &v[(corner + 1) % 3] - &v[corner];
+            let edge2 = &v[(corner + 2) % 3] - &v[corner];
+            let angle = edge1.cross(&edge2).length().atan2(edge1.dot(&edge2));
+            corners.entry(key(&v[corner])).or_default().push((index, angle));
         }
     }
 
-    fn new(triangles: Vec<Triangle>, texture: Arc<Rgb32FImage>) -> Split {
-        Split::new_int(triangles, texture, false, false, false)
-    }
-
-    fn triangles_intersects(triangles: &Vec<Triangle>, texture: &Rgb32FImage, start: &Vec3, ray: &Vec3) -> Option<IntersectionResult> {
-        let mut result: Option<IntersectionResult> = None;
-        for triangle in triangles {
-            result = match Split::triangle_intersects(triangle, texture, start, ray) {
-                None => result,
-                Some(intersection) => {
-                    match result {
-                        None => Some(intersection),
-                        Some(best_intersection) => if intersection.distance < best_intersection.distance {
-                            Some(intersection)
-                        } else {
-                            Some(best_intersection)
-                        }
-                    }
+    let min_cos = crease_angle.to_radians().cos();
+    triangles.iter().enumerate().map(|(index, v)| {
+        let face_norm = &face_norms[index];
+        [0, 1, 2].map(|corner| {
+            let mut sum = Vec3::new(0.0, 0.0, 0.0);
+            for &(other, angle) in &corners[&key(&v[corner])] {
+                // The corner's own face always counts, even with a crease angle of 0
+                if other == index || face_norms[other].dot(face_norm) >= min_cos {
+                    sum += &face_norms[other] * angle;
                 }
             }
-        }
-        result
-    }
-
-    fn triangle_intersects(triangle: &Triangle, texture: &Rgb32FImage, start: &Vec3, ray: &Vec3) -> Option<IntersectionResult> {
-        let pvec = ray.cross(&triangle.edge2);
-
-        let det = triangle.edge1.dot(&pvec);
-        
-        /*
-        if det < 1e-7 && det > -1e-7 {
-            return None
-        }
-        */
-
-
-        let tvec = start - &triangle.v0;
-        let u = tvec.dot(&pvec);
-        if u < 0.0 || u > det {
-            return None;
-        }
-
-        let qvec = tvec.cross(&triangle.edge1);
-        let v = ray.dot(&qvec);
-        if v < 0.0 || u + v > det {
-            return None;
-        }
-
-        let t = triangle.edge2.dot(&qvec);
-        let inv_det = 1.0 / det;
-        let t = t * inv_det;
-        let u = u * inv_det;
-        let v = v * inv_det;
-
-        let tmp_norm = &triangle.norm;
-        let norm = if tmp_norm.dot(&ray) > 0.0 {
-            -tmp_norm.clone()
-        } else {
-            tmp_norm.clone()
-        };
-
-        let tex_pos = (1.0 - u - v) * &triangle.tex_v0 + u * &triangle.tex_v1 + v * &triangle.tex_v2;
-        let color = texture.get_pixel((texture.width() as f32 * tex_pos.x()) as u32,
-                                      (texture.height() as f32 * tex_pos.y()) as u32);
-        let color = [color[0], color[1], color[2]];
-        Some(IntersectionResult::new(t, t, norm).set_color(color))
-    }
-}
-
-#[typetag::serde(name="split")]
-impl Shape for Split {
-    fn intersects(&self, start: &Vec3, ray: &Vec3) -> Option<IntersectionResult> {
-        match self {
-            Split::Leaf{triangles, texture} => {
-                Split::triangles_intersects(triangles, &texture.as_ref().unwrap(), start, ray)
-            },
-            Split::Node{left, left_box, right, right_box} => {
-                let left_intersect: Option<IntersectionResult> = left_box.intersects(start, ray);
-                let right_intersect: Option<IntersectionResult> = right_box.intersects(start, ray);
-                match left_intersect {
-                    None => match right_intersect {
-                        None => None,
-                        Some(_) => right.intersects(start, ray)
-                    },
-                    Some(left_result) => match right_intersect {
-                        None => left.intersects(start, ray),
-                        Some(right_result) => {
-                            let (first, second) = if left_result.distance < right_result.distance {
-                                (&left, &right)
-                            } else {
-                                (&right, &left)
-                            };
-                            match first.intersects(start, ray) {
-                                None => second.intersects(start, ray),
-                                Some(v) => Some(v)
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
-
-    fn init(&mut self) {
-    }
+            sum
+        })
+    }).collect()
 }
 
-#[derive(Serialize,Deserialize,Default,Derivative,Debug)]
+#[derive(Serialize,Deserialize,Default,Derivative)]
+#[derivative(Debug)]
 pub struct Obj {
     #[serde(skip_serializing,skip_deserializing)]
     #[derivative(Debug="ignore")]
-    bbox: Object,
-    //triangles: Vec<[Vec3; 3]>
-    /*
+    triangles: Vec<Triangle>,
+
+    /// Triangle areas, for picking triangles in proportion to their size
     #[serde(skip_serializing,skip_deserializing)]
     #[derivative(Debug="ignore")]
-    triangles: Vec<Triangle>,
-    */
+    areas: Distribution,
+
     #[serde(skip_serializing,skip_deserializing)]
-    split: Split,
+    #[derivative(Debug="ignore")]
+    bvh: Bvh,
 
     #[serde(skip_serializing,skip_deserializing)]
     #[derivative(Debug="ignore")]
-    texture: Arc<Rgb32FImage>,
+    texture: ImageTexture,
 
     filepath: String,
-    texture_path: String,
+    /// Image colouring the model by its texture coordinates, in place of the material's color
+    #[serde(default)]
+    texture_path: Option<String>,
+    #[serde(flatten)]
+    sampler: Sampler,
+    /// Pixel values are used as they are unless set to `srgb`, which most 8-bit images are in
+    #[serde(default)]
+    texture_color_space: ColorSpace,
+    /// Faces meeting at a sharper angle, in degrees, keep a hard edge between them when the
+    /// file has no vertex normals. 0 keeps the model faceted.
+    #[serde(default = "Obj::default_crease_angle")]
+    crease_angle: f32,
+    /// Max triangles in a BVH leaf
+    #[serde(default = "Obj::default_leaf_size")]
+    leaf_size: usize,
 }
 
 impl Obj {
-    /*
-    fn triangles_intersects(&self, start: &Vec3, ray: &Vec3) -> Option<IntersectionResult> {
-        let mut result: Option<IntersectionResult> = None;
-        for triangle in &self.triangles {
-            result = match self.triangle_intersects(triangle, start, ray) {
-                None => result,
-                Some(intersection) => {
-                    match result {
-                        None => Some(intersection),
-                        Some(best_intersection) => if intersection.distance < best_intersection.distance {
-                            Some(intersection)
-                        } else {
-                            Some(best_intersection)
-                        }
-                    }
-                }
-            }
-        }
-        result
+    pub fn default_leaf_size() -> usize {
+        Bvh::DEFAULT_LEAF_SIZE
+    }
+
+    pub fn default_crease_angle() -> f32 {
+        60.0
     }
 
     fn triangle_intersects(&self, triangle: &Triangle, start: &Vec3, ray: &Vec3) -> Option<IntersectionResult> {
         let pvec = ray.cross(&triangle.edge2);
 
         let det = triangle.edge1.dot(&pvec);
-        
-        /*
-        if det < 1e-7 && det > -1e-7 {
-            return None
+        // Both sides are hit, rays refracted into a closed mesh have to find its back faces
+        if det.abs() < 1e-12 {
+            return None;
         }
-        */
-
+        let inv_det = 1.0 / det;
 
         let tvec = start - &triangle.v0;
-        let u = tvec.dot(&pvec);
-        if u < 0.0 || u > det {
+        let u = tvec.dot(&pvec) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
             return None;
         }
 
         let qvec = tvec.cross(&triangle.edge1);
-        let v = ray.dot(&qvec);
-        if v < 0.0 || u + v > det {
+        let v = ray.dot(&qvec) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
             return None;
         }
 
-        let t = triangle.edge2.dot(&qvec);
-        let inv_det = 1.0 / det;
-        let t = t * inv_det;
-        let u = u * inv_det;
-        let v = v * inv_det;
+        let t = triangle.edge2.dot(&qvec) * inv_det;
+        if t < 0.0 {
+            return None;
+        }
 
-        let tmp_norm = &triangle.norm;
-        let norm = if tmp_norm.dot(&ray) > 0.0 {
-            -tmp_norm.clone()
-        } else {
-            tmp_norm.clone()
+        // The interpolated normal is used while it is on the same side of the ray as the face,
+        // so hits from inside stay smooth and near silhouettes shading never flips sides
+        let norm = match &triangle.vertex_norms {
+            Some([n0, n1, n2]) => {
+                let smooth = (n0 * (1.0 - u - v) + n1 * u + n2 * v).norm();
+                if (smooth.dot(ray) < 0.0) == (triangle.norm.dot(ray) < 0.0) { smooth } else { triangle.norm.clone() }
+            },
+            None => triangle.norm.clone(),
         };
 
         let tex_pos = (1.0 - u - v) * &triangle.tex_v0 + u * &triangle.tex_v1 + v * &triangle.tex_v2;
-        let color = self.texture.get_pixel((self.texture.width() as f32 * tex_pos.x()) as u32,
-                                           (self.texture.height() as f32 * tex_pos.y()) as u32);
-        let color = [color[0], color[1], color[2]];
-        Some(IntersectionResult::new(t, t, norm).set_color(color))
+        let uv = (tex_pos.x(), tex_pos.y());
+        let intersection = IntersectionResult::new(t, t, norm);
+        match &triangle.derivatives {
+            Some((dpdu, dpdv)) => Some(intersection.set_mapping(start + ray * t, uv, dpdu.clone(), dpdv.clone())),
+            None => Some(intersection.set_uv(uv)),
+        }
     }
-*/
 }
 
 #[typetag::serde(name="object")]
 impl Shape for Obj {
-    fn intersects(&self, start: &Vec3, ray: &Vec3) -> Option<IntersectionResult> {
-        /*
-        match self.bbox.intersects(start, ray) {
-            None => None,
-            Some((intersection, _)) => Some(intersection)
-        }
-        */
-        match self.bbox.intersects(start, ray) {
-            None => None,
-            //Some(_) => self.triangles_intersects(start, ray)
-            Some(_) => self.split.intersects(start, ray)
-        }
+    fn intersects(&self, start: &Vec3, ray: &Vec3, t_min: f32) -> Option<IntersectionResult> {
+        self.bvh.closest_hit(start, ray, t_min.max(0.0), f32::INFINITY, |index| {
+            self.triangle_intersects(&self.triangles[index], start, ray)
+                .map(|intersection| (intersection.distance, intersection))
+        }).map(|(_, intersection)| intersection)
     }
 
     fn init(&mut self) {
@@ -429,9 +214,11 @@ impl Shape for Obj {
             ignore_points: false,
             ignore_lines: false,
         };
-        let (models, _materials) = tobj::load_obj(&self.filepath, &load_opts).expect(&format!("Failed to load obj file {}", self.filepath));
-        self.texture = Arc::new(ImageReader::open(&self.texture_path).expect(&format!("Failed to open file {}", self.texture_path))
-                                                    .decode().expect(&format!("Failed to decoded file {}", self.texture_path)).into_rgb32f());
+        let (models, _materials) = tobj::load_obj(&self.filepath, &load_opts).unwrap_or_else(|_| panic!("Failed to load obj file {}", self.filepath));
+        if let Some(texture_path) = &self.texture_path {
+            self.texture = ImageTexture::new(texture_path.clone(), self.sampler, Some(self.texture_color_space));
+            self.texture.init();
+        }
         // TODO: materials
 
         let mut min_x: Option<f32> = None;
@@ -469,18 +256,12 @@ impl Shape for Obj {
             let y = mesh.positions[index * 3 + 1];
             let z = mesh.positions[index * 3 + 2];
 
+            // Coordinates outside of [0, 1] are left to the texture's wrap mode
             let mut u: f32 = 0.0;
             let mut v: f32 = 0.0;
             if index * 2 + 1 < mesh.texcoords.len() {
-                u = mesh.texcoords[index * 2].fract();
-                if u < 0.0 {
-                    u = 1.0 + u;
-                }
-
-                v = mesh.texcoords[index * 2 + 1].fract();
-                if v < 0.0 {
-                    v = 1.0 + v;
-                }
+                u = mesh.texcoords[index * 2];
+                v = mesh.texcoords[index * 2 + 1];
             }
 
             assign_min(&mut min_x, x);
@@ -494,11 +275,18 @@ impl Shape for Obj {
             (Vec3::new(x, y, z), Vec3::new(u, v, 0.0))
         };
 
+        let normal_loader = |index: usize, mesh: &tobj::Mesh| -> Vec3 {
+            Vec3::new(mesh.normals[index * 3], mesh.normals[index * 3 + 1], mesh.normals[index * 3 + 2])
+        };
+
         println!("There are {} meshes", models.len());
         let mut triangles: Vec<[Vec3; 6]> = Vec::new();
+        let mut normals: Vec<[Vec3; 3]> = Vec::new();
         for model in models {
             let mesh = &model.mesh;
             println!("{} triangles", mesh.indices.len() / 3);
+            let first = triangles.len();
+            let has_normals = !mesh.normals.is_empty();
 
             for i in 0 .. mesh.indices.len() / 3 {
                 let i1 = mesh.indices[i * 3];
@@ -510,6 +298,14 @@ impl Shape for Obj {
                 let (v3, tex_v3) = index_loader(i3 as usize, mesh);
                 
                 triangles.push([v1, v2, v3, tex_v1, tex_v2, tex_v3]);
+                if has_normals {
+                    normals.push([normal_loader(i1 as usize, mesh),
+                                  normal_loader(i2 as usize, mesh),
+                                  normal_loader(i3 as usize, mesh)]);
+                }
+            }
+            if !has_normals {
+                normals.extend(smooth_normals(&triangles[first..], self.crease_angle));
             }
         }
 
@@ -525,17 +321,39 @@ impl Shape for Obj {
 
         let xyz_move = Vec3::new(x_move, y_move, z_move);
 
-        let triangles = triangles.iter().map(|v: &[Vec3; 6]| -> Triangle {
+        // Scaling is the same along every axis, so the normals are unchanged
+        let triangles: Vec<Triangle> = triangles.iter().zip(normals).map(|(v, norms): (&[Vec3; 6], [Vec3; 3])| -> Triangle {
             let v1 = &v[0] * scale - &xyz_move;
             let v2 = &v[1] * scale - &xyz_move;
             let v3 = &v[2] * scale - &xyz_move;
             let tex_v1 = &v[3];
             let tex_v2 = &v[4];
             let tex_v3 = &v[5];
-            Triangle::new(&v1, &v2, &v3, tex_v1.clone(), tex_v2.clone(), tex_v3.clone())
+            Triangle::new(&v1, &v2, &v3, tex_v1.clone(), tex_v2.clone(), tex_v3.clone(), Some(norms))
         }).collect();
 
-        self.bbox = Object::new(Box::new(Cube::default())).set_size(Vec3::new(scale / x_scale, scale / y_scale, scale / z_scale));
-        self.split = Split::new(triangles, self.texture.clone());
+        let bounds: Vec<Aabb> = triangles.iter().map(|triangle| triangle.bounds()).collect();
+        self.bvh = Bvh::new(&bounds, self.leaf_size);
+        let areas: Vec<f32> = triangles.iter().map(|triangle| triangle.norm.length() / 2.0).collect();
+        self.areas = Distribution::new(&areas);
+        self.triangles = triangles;
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.bvh.bounds()
+    }
+
+    fn sample_surface(&self, u: f32, v: f32) -> Option<SurfaceSample> {
+        // `u` picks a triangle and is then reused inside it
+        let (index, u) = self.areas.sample(u)?;
+        let triangle = &self.triangles[index];
+
+        let a = u.sqrt();
+        let point = &triangle.v0 + &triangle.edge1 * (a * (1.0 - v)) + &triangle.edge2 * (a * v);
+        Some(SurfaceSample{point, norm: triangle.norm.norm(), area: self.areas.total()})
+    }
+
+    fn texture(&self) -> Option<&dyn Texture> {
+        self.texture_path.as_ref().map(|_| &self.texture as &dyn Texture)
     }
 }
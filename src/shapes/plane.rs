@@ -1,6 +1,7 @@
 use super::shape::Shape;
-use super::shape::IntersectionResult;
+use super::shape::{IntersectionResult,SurfaceSample,face_axes};
 use crate::vec::Vec3;
+use crate::bvh::Aabb;
 use serde::{Serialize,Deserialize};
 
 #[derive(Serialize,Deserialize,Debug)]
@@ -25,7 +26,7 @@ pub struct Plane {
 
 #[typetag::serde(name="plane")]
 impl Shape for Plane {
-    fn intersects(&self, start: &Vec3, ray: &Vec3) -> Option<IntersectionResult> {
+    fn intersects(&self, start: &Vec3, ray: &Vec3, _t_min: f32) -> Option<IntersectionResult> {
         let c = ray.dot(&self.norm);
         if c == 0.0 {
             return None;
@@ -38,7 +39,6 @@ impl Shape for Plane {
         let point = start + ray * t;
 
         //let point2 = &self.point + &self.direction1 + &self.direction2;
-        let eps = 1e-5;
         /*
         if point.x() < f32::min(self.point.x(), point2.x()) - eps
             || point.x() > f32::max(self.point.x(), point2.x()) + eps
@@ -56,7 +56,39 @@ impl Shape for Plane {
         {
                 return None;
         }
-        Some(IntersectionResult::new(t, t, self.norm.clone()))
+        // Planar mapping, one texture repetition per unit, starting at the corner of the bounds
+        let (u_axis, v_axis) = face_axes(&self.norm);
+        let offset = &point - &self.min_point;
+        let uv = (offset.dot(&u_axis), offset.dot(&v_axis));
+        Some(IntersectionResult::new(t, t, self.norm.clone()).set_mapping(point, uv, u_axis, v_axis))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.min_point.clone(), self.max_point.clone()))
+    }
+
+    fn sample_surface(&self, u: f32, v: f32) -> Option<SurfaceSample> {
+        // Uniform over the bounds seen along the axis the plane faces most, lifted onto the plane.
+        // Points lifted outside the bounds of a tilted plane miss it, the area covers them as well
+        let axis = (0..3).max_by(|&i, &j| self.norm[i].abs().total_cmp(&self.norm[j].abs())).unwrap_or(0);
+        if self.norm[axis] == 0.0 {
+            return None;
+        }
+        let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+        let mut point = [0.0; 3];
+        point[a] = self.min_point[a] + u * (self.max_point[a] - self.min_point[a]);
+        point[b] = self.min_point[b] + v * (self.max_point[b] - self.min_point[b]);
+        point[axis] = -(self.d + self.norm[a] * point[a] + self.norm[b] * point[b]) / self.norm[axis];
+        if point[axis] < self.min_point[axis] || point[axis] > self.max_point[axis] {
+            return None;
+        }
+        let area = (self.max_point[a] - self.min_point[a]) * (self.max_point[b] - self.min_point[b])
+            / self.norm[axis].abs();
+        Some(SurfaceSample {
+            point: Vec3::new(point[0], point[1], point[2]),
+            norm: self.norm.clone(),
+            area,
+        })
     }
 }
 
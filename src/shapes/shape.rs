@@ -1,4 +1,6 @@
 use crate::vec::Vec3;
+use crate::bvh::Aabb;
+use crate::texture::Texture;
 use serde::{Serialize,Deserialize};
 
 #[derive(Clone)]
@@ -6,28 +8,102 @@ pub struct IntersectionResult {
     pub distance: f32,
     pub max_distance: f32,
     pub norm: Vec3,
-    pub color: Option<[f32; 3]>,
+    pub max_norm: Vec3,
+    /// Hit point in the space of the shape that was hit
+    pub point: Option<Vec3>,
+    /// Texture coordinates of the hit, for shapes that have them
+    pub uv: Option<(f32, f32)>,
+    /// Texture coordinate units per object space unit around the hit, for filtering textures
+    pub uv_density: f32,
+    /// ∂p/∂u and ∂p/∂v in object space, for orienting normal maps
+    pub derivatives: Option<(Vec3, Vec3)>,
 }
 
 
 impl IntersectionResult {
     pub fn new(distance: f32, max_distance: f32, norm: Vec3) -> IntersectionResult {
-        IntersectionResult{distance, max_distance, norm, color: None}
+        IntersectionResult{distance, max_distance, max_norm: norm.clone(), norm, point: None, uv: None, uv_density: 0.0, derivatives: None}
     }
 
-    pub fn set_color(mut self, color: [f32; 3]) -> IntersectionResult {
-        self.color = Some(color);
+    pub fn set_max_norm(mut self, max_norm: Vec3) -> IntersectionResult {
+        self.max_norm = max_norm;
         self
     }
+
+    pub fn set_uv(mut self, uv: (f32, f32)) -> IntersectionResult {
+        self.uv = Some(uv);
+        self
+    }
+
+    /// Texture mapping at the hit `point`, with the texture coordinate density following from
+    /// the derivatives. Degenerate derivatives, as at the poles of a sphere, are left out.
+    pub fn set_mapping(mut self, point: Vec3, uv: (f32, f32), dpdu: Vec3, dpdv: Vec3) -> IntersectionResult {
+        let area = dpdu.cross(&dpdv).length();
+        self.point = Some(point);
+        self.uv = Some(uv);
+        if area > 1e-8 {
+            self.uv_density = 1.0 / area.sqrt();
+            self.derivatives = Some((dpdu, dpdv));
+        }
+        self
+    }
+
+    /// Moves the hit to the exit surface, whose mapping isn't known
+    pub fn use_exit(&mut self) {
+        self.distance = self.max_distance;
+        self.norm = self.max_norm.clone();
+        self.point = None;
+        self.uv = None;
+        self.uv_density = 0.0;
+        self.derivatives = None;
+    }
+}
+
+/// Directions of growing texture coordinates u and v on a flat face with the outward unit
+/// normal `norm`. Seen from outside, u runs to the right and v down, like image columns and rows.
+/// Top and bottom faces have +z at the top of the image.
+pub fn face_axes(norm: &Vec3) -> (Vec3, Vec3) {
+    let image_up = if norm.y().abs() > norm.x().abs().max(norm.z().abs()) {
+        Vec3::new(0.0, 0.0, 1.0)
+    } else {
+        Vec3::new(0.0, -1.0, 0.0)
+    };
+    let u_axis = image_up.cross(norm).norm();
+    let v_axis = u_axis.cross(norm);
+    (u_axis, v_axis)
+}
+
+/// Point picked on the surface of a shape
+pub struct SurfaceSample {
+    pub point: Vec3,
+    /// Outward unit normal
+    pub norm: Vec3,
+    /// Surface area the sample stands for, the inverse of its probability density
+    pub area: f32,
 }
 
 
 #[typetag::serde(tag="type")]
 pub trait Shape: std::fmt::Debug + Sync + Send {
-    fn intersects(&self, start: &Vec3, ray: &Vec3) -> Option<IntersectionResult>;
+    /// Entry and exit of the ray `start + t * ray`. Shapes made of many surfaces, such as meshes,
+    /// return the first one hit at `t_min` or later, closed convex shapes the whole span.
+    fn intersects(&self, start: &Vec3, ray: &Vec3, t_min: f32) -> Option<IntersectionResult>;
     fn init(&mut self) {
         // empty
     }
+    /// Bounding box in object space, `None` if the shape is unbounded
+    fn bounds(&self) -> Option<Aabb> {
+        None
+    }
+    /// Point spread uniformly over the surface for `u` and `v` uniform in [0, 1),
+    /// `None` if the shape can't be sampled
+    fn sample_surface(&self, _u: f32, _v: f32) -> Option<SurfaceSample> {
+        None
+    }
+    /// Texture coloring the shape whatever its material, such as the image of an OBJ model
+    fn texture(&self) -> Option<&dyn Texture> {
+        None
+    }
 }
 
 #[derive(Serialize,Deserialize,Debug)]
@@ -42,7 +118,7 @@ impl NoneShape {
 
 #[typetag::serde(name="none")]
 impl Shape for NoneShape {
-    fn intersects(&self, _start: &Vec3, _ray: &Vec3) -> Option<IntersectionResult> {
+    fn intersects(&self, _start: &Vec3, _ray: &Vec3, _t_min: f32) -> Option<IntersectionResult> {
         None
     }
 }
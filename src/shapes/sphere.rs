@@ -1,8 +1,12 @@
 use super::shape::Shape;
-use super::shape::IntersectionResult;
+use super::shape::{IntersectionResult,SurfaceSample};
 use crate::vec::Vec3;
+use crate::bvh::Aabb;
+use crate::sampling::uniform_sphere;
 use serde::{Serialize,Deserialize};
 
+use std::f32::consts::PI;
+
 
 #[derive(Serialize,Deserialize,Debug)]
 pub struct Sphere {
@@ -10,15 +14,15 @@ pub struct Sphere {
 
 #[typetag::serde(name="sphere")]
 impl Shape for Sphere {
-    fn intersects(&self, start: &Vec3, ray: &Vec3) -> Option<IntersectionResult> {
+    fn intersects(&self, start: &Vec3, ray: &Vec3, _t_min: f32) -> Option<IntersectionResult> {
         let oc = start; // - 0.0,0.0,0.0 (start pos)
         let k1 = ray.dot(ray);
-        let k2 = 2. * &oc.dot(&ray);
+        let k2 = 2. * oc.dot(ray);
         let radius = 0.5;
-        let k3 = &oc.dot(&oc) - radius * radius;
+        let k3 = oc.dot(oc) - radius * radius;
         let d = k2 * k2 - 4. * k1 * k3;
         if d < 0.0 {
-            return None;
+            None
         } else {
             let t1 = (-k2 + d.sqrt()) / (2.0 * k1);
             let t2 = (-k2 - d.sqrt()) / (2.0 * k1);
@@ -27,9 +31,20 @@ impl Shape for Sphere {
             let max_t = f32::max(t1, t2);
             let point = start + ray * min_t;
             let norm = point.norm();
-            return Some(IntersectionResult::new(min_t, max_t, norm));
+            let max_norm = (start + ray * max_t).norm();
+            let (uv, dpdu, dpdv) = Sphere::mapping(&point);
+            Some(IntersectionResult::new(min_t, max_t, norm).set_max_norm(max_norm).set_mapping(point, uv, dpdu, dpdv))
         }
     }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(Aabb::new(Vec3::new(-0.5, -0.5, -0.5), Vec3::new(0.5, 0.5, 0.5)))
+    }
+
+    fn sample_surface(&self, u: f32, v: f32) -> Option<SurfaceSample> {
+        let norm = uniform_sphere(u, v);
+        Some(SurfaceSample{point: &norm * 0.5, norm, area: PI})
+    }
 }
 
 impl Sphere {
@@ -37,4 +52,19 @@ impl Sphere {
     pub fn new() -> Sphere {
         Sphere{}
     }
+
+    /// Latitude-longitude texture coordinates of a point on the sphere with ∂p/∂u and ∂p/∂v.
+    /// u goes once around the up axis with the middle of the image on the -z side, facing a
+    /// camera looking along +z, and v from the top pole down to the bottom one.
+    fn mapping(point: &Vec3) -> ((f32, f32), Vec3, Vec3) {
+        let radius = 0.5;
+        let cos_theta = (-point.y() / radius).clamp(-1.0, 1.0);
+        let theta = cos_theta.acos();
+        let phi = point.x().atan2(-point.z());
+        let uv = (0.5 + phi / (2.0 * PI), theta / PI);
+
+        let dpdu = Vec3::new(-point.z(), 0.0, point.x()) * (2.0 * PI);
+        let dpdv = Vec3::new(cos_theta * phi.sin(), theta.sin(), -cos_theta * phi.cos()) * (radius * PI);
+        (uv, dpdu, dpdv)
+    }
 }
@@ -0,0 +1,351 @@
+use crate::material::SurfacePoint;
+use serde::{Serialize,Deserialize};
+use derivative::Derivative;
+use image::io::Reader as ImageReader;
+use image::codecs::hdr::HdrDecoder;
+use image::{ImageResult,Rgb32FImage};
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::{Arc,Mutex,OnceLock};
+
+/// Pattern giving material parameters a value at each point of a surface
+#[typetag::serde(tag="type")]
+pub trait Texture: std::fmt::Debug + Sync + Send {
+    fn init(&mut self) {
+        // empty
+    }
+    /// `init` for textures holding data such as normals rather than colors,
+    /// images without a `color_space` are read as linear
+    fn init_data(&mut self) {
+        self.init()
+    }
+    /// Linear RGB at the surface point
+    fn color(&self, surface: &SurfacePoint) -> [f32; 3];
+    /// Single value at the surface point, for scalar parameters such as roughness
+    fn value(&self, surface: &SurfacePoint) -> f32 {
+        let color = self.color(surface);
+        (color[0] + color[1] + color[2]) / 3.0
+    }
+}
+
+/// Material color, constant or looked up in a texture
+#[derive(Serialize,Deserialize,Debug)]
+#[serde(untagged)]
+pub enum ColorParam {
+    Constant([f32; 3]),
+    Texture(Box<dyn Texture>),
+}
+
+impl ColorParam {
+    pub fn white() -> ColorParam {
+        ColorParam::Constant([1.0, 1.0, 1.0])
+    }
+
+    pub fn init(&mut self) {
+        if let ColorParam::Texture(texture) = self {
+            texture.init();
+        }
+    }
+
+    pub fn color(&self, surface: &SurfacePoint) -> [f32; 3] {
+        match self {
+            ColorParam::Constant(color) => *color,
+            ColorParam::Texture(texture) => texture.color(surface),
+        }
+    }
+}
+
+impl From<[f32; 3]> for ColorParam {
+    fn from(color: [f32; 3]) -> ColorParam {
+        ColorParam::Constant(color)
+    }
+}
+
+/// Scalar material parameter, constant or looked up in a texture
+#[derive(Serialize,Deserialize,Debug)]
+#[serde(untagged)]
+pub enum ScalarParam {
+    Constant(f32),
+    Texture(Box<dyn Texture>),
+}
+
+impl ScalarParam {
+    pub fn init(&mut self) {
+        if let ScalarParam::Texture(texture) = self {
+            texture.init();
+        }
+    }
+
+    pub fn value(&self, surface: &SurfacePoint) -> f32 {
+        match self {
+            ScalarParam::Constant(value) => *value,
+            ScalarParam::Texture(texture) => texture.value(surface),
+        }
+    }
+}
+
+impl Default for ScalarParam {
+    fn default() -> ScalarParam {
+        ScalarParam::Constant(0.0)
+    }
+}
+
+impl From<f32> for ScalarParam {
+    fn from(value: f32) -> ScalarParam {
+        ScalarParam::Constant(value)
+    }
+}
+
+/// What texture coordinates outside of [0, 1] read
+#[derive(Serialize,Deserialize,Default,Clone,Copy,PartialEq,Debug)]
+#[serde(rename_all="lowercase")]
+pub enum Wrap {
+    /// The image tiles the plane
+    #[default]
+    Repeat,
+    /// The edge pixels stretch outward
+    Clamp,
+    /// The image tiles the plane, every other copy flipped
+    Mirror,
+}
+
+impl Wrap {
+    /// Pixel index for the possibly out of range `index` along a side `size` pixels long
+    fn apply(&self, index: i64, size: i64) -> i64 {
+        match self {
+            Wrap::Repeat => index.rem_euclid(size),
+            Wrap::Clamp => index.clamp(0, size - 1),
+            Wrap::Mirror => {
+                let index = index.rem_euclid(2 * size);
+                if index >= size { 2 * size - 1 - index } else { index }
+            },
+        }
+    }
+}
+
+#[derive(Serialize,Deserialize,Default,Clone,Copy,PartialEq,Debug)]
+#[serde(rename_all="lowercase")]
+pub enum TextureFilter {
+    /// Closest pixel
+    Nearest,
+    /// Blend of the four closest pixels
+    Bilinear,
+    /// Bilinear lookups in the two mip levels closest to the size of the ray footprint, blended
+    #[default]
+    Trilinear,
+}
+
+/// How pixels of an image are decoded to linear values
+#[derive(Serialize,Deserialize,Default,Clone,Copy,PartialEq,Eq,Hash,Debug)]
+#[serde(rename_all="lowercase")]
+pub enum ColorSpace {
+    Srgb,
+    /// Pixel values are used as they are
+    #[default]
+    Linear,
+}
+
+impl ColorSpace {
+    /// Linear for the float formats, sRGB for the others
+    fn guess(path: &str) -> ColorSpace {
+        let extension = Path::new(path).extension().and_then(|extension| extension.to_str()).unwrap_or("");
+        if ["hdr", "exr"].iter().any(|float| extension.eq_ignore_ascii_case(float)) {
+            ColorSpace::Linear
+        } else {
+            ColorSpace::Srgb
+        }
+    }
+
+    fn decode(&self, value: f32) -> f32 {
+        match self {
+            ColorSpace::Linear => value,
+            ColorSpace::Srgb => if value <= 0.04045 {
+                value / 12.92
+            } else {
+                ((value + 0.055) / 1.055).powf(2.4)
+            },
+        }
+    }
+}
+
+/// How an image is looked up
+#[derive(Serialize,Deserialize,Default,Clone,Copy,Debug)]
+pub struct Sampler {
+    #[serde(default)]
+    pub wrap: Wrap,
+    #[serde(default)]
+    pub filter: TextureFilter,
+}
+
+/// Image with its successively halved copies, for filtering footprints larger than a pixel
+#[derive(Default)]
+pub struct MipMap {
+    levels: Vec<Rgb32FImage>,
+}
+
+impl MipMap {
+    pub fn new(image: Rgb32FImage) -> MipMap {
+        let mut levels = vec![image];
+        loop {
+            let image = levels.last().expect("The full image is the first level");
+            let (width, height) = image.dimensions();
+            if width <= 1 && height <= 1 {
+                break;
+            }
+            let (half_width, half_height) = ((width / 2).max(1), (height / 2).max(1));
+            let half = Rgb32FImage::from_fn(half_width, half_height, |x, y| {
+                let mut sum = [0.0; 3];
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let pixel = image.get_pixel((2 * x + dx).min(width - 1), (2 * y + dy).min(height - 1));
+                    for (s, c) in sum.iter_mut().zip(pixel.0) {
+                        *s += c / 4.0;
+                    }
+                }
+                image::Rgb(sum)
+            });
+            levels.push(half);
+        }
+        MipMap{levels}
+    }
+
+    fn texel(&self, level: usize, x: i64, y: i64, wrap: Wrap) -> [f32; 3] {
+        let image = &self.levels[level];
+        let x = wrap.apply(x, image.width() as i64);
+        let y = wrap.apply(y, image.height() as i64);
+        image.get_pixel(x as u32, y as u32).0
+    }
+
+    fn bilinear(&self, level: usize, u: f32, v: f32, wrap: Wrap) -> [f32; 3] {
+        let image = &self.levels[level];
+        // Pixel centers are at half-integer coordinates
+        let x = u * image.width() as f32 - 0.5;
+        let y = v * image.height() as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = lerp(&self.texel(level, x0, y0, wrap), &self.texel(level, x0 + 1, y0, wrap), fx);
+        let bottom = lerp(&self.texel(level, x0, y0 + 1, wrap), &self.texel(level, x0 + 1, y0 + 1, wrap), fx);
+        lerp(&top, &bottom, fy)
+    }
+
+    /// Color at the texture coordinates `(u, v)`, `footprint` is the width of the area seen
+    /// by the ray in texture coordinates
+    pub fn sample(&self, u: f32, v: f32, footprint: f32, sampler: &Sampler) -> [f32; 3] {
+        let Some(image) = self.levels.first() else {
+            return [0.0; 3];
+        };
+        match sampler.filter {
+            TextureFilter::Nearest => {
+                let x = (u * image.width() as f32).floor() as i64;
+                let y = (v * image.height() as f32).floor() as i64;
+                self.texel(0, x, y, sampler.wrap)
+            },
+            TextureFilter::Bilinear => self.bilinear(0, u, v, sampler.wrap),
+            TextureFilter::Trilinear => {
+                let pixels = footprint * image.width().max(image.height()) as f32;
+                let level = pixels.max(1.0).log2().min((self.levels.len() - 1) as f32);
+                let fine = level.floor() as usize;
+                let coarse = (fine + 1).min(self.levels.len() - 1);
+                lerp(&self.bilinear(fine, u, v, sampler.wrap),
+                     &self.bilinear(coarse, u, v, sampler.wrap), level - fine as f32)
+            },
+        }
+    }
+}
+
+fn lerp(a: &[f32; 3], b: &[f32; 3], t: f32) -> [f32; 3] {
+    [0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * t)
+}
+
+/// Reads an image as floats without decoding its color space. Radiance files need their
+/// own decoder, the generic one converts them to 8 bits.
+pub fn load_image(path: &str) -> ImageResult<Rgb32FImage> {
+    let is_hdr = Path::new(path).extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("hdr"));
+    if is_hdr {
+        let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+        let metadata = decoder.metadata();
+        let pixels: Vec<f32> = decoder.read_image_hdr()?.into_iter().flat_map(|pixel| pixel.0).collect();
+        Ok(Rgb32FImage::from_raw(metadata.width, metadata.height, pixels).expect("Decoder returns whole images"))
+    } else {
+        Ok(ImageReader::open(path)?.decode()?.into_rgb32f())
+    }
+}
+
+/// Mip maps by image path and the color space they were decoded from
+type TextureCache = HashMap<(String, ColorSpace), Arc<MipMap>>;
+
+/// Mip maps of the images loaded so far, shared by every object using them
+fn texture_cache() -> &'static Mutex<TextureCache> {
+    static CACHE: OnceLock<Mutex<TextureCache>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+/// Mip map of the image at `path` decoded from `color_space`, loaded once per process
+pub fn load_texture(path: &str, color_space: ColorSpace) -> Arc<MipMap> {
+    let mut cache = texture_cache().lock().expect("Texture loading doesn't panic with the lock held");
+    cache.entry((path.to_string(), color_space)).or_insert_with(|| {
+        let mut image = load_image(path).unwrap_or_else(|_| panic!("Failed to load image {}", path));
+        for pixel in image.pixels_mut() {
+            pixel.0 = pixel.0.map(|c| color_space.decode(c));
+        }
+        Arc::new(MipMap::new(image))
+    }).clone()
+}
+
+/// Image mapped onto the surface by its texture coordinates
+#[derive(Serialize,Deserialize,Default,Derivative)]
+#[derivative(Debug)]
+pub struct ImageTexture {
+    path: String,
+    #[serde(flatten)]
+    sampler: Sampler,
+    /// Linear for `.hdr` and `.exr` files and sRGB for others when missing
+    #[serde(default)]
+    color_space: Option<ColorSpace>,
+    /// Repetitions of the image per unit of texture coordinates
+    #[serde(default = "ImageTexture::default_scale")]
+    scale: [f32; 2],
+
+    #[serde(skip_serializing,skip_deserializing)]
+    #[derivative(Debug="ignore")]
+    image: Arc<MipMap>,
+}
+
+impl ImageTexture {
+    pub fn new(path: String, sampler: Sampler, color_space: Option<ColorSpace>) -> ImageTexture {
+        ImageTexture {
+            path,
+            sampler,
+            color_space,
+            scale: ImageTexture::default_scale(),
+            image: Arc::default(),
+        }
+    }
+
+    pub fn default_scale() -> [f32; 2] {
+        [1.0, 1.0]
+    }
+}
+
+#[typetag::serde(name="image")]
+impl Texture for ImageTexture {
+    fn init(&mut self) {
+        let color_space = self.color_space.unwrap_or_else(|| ColorSpace::guess(&self.path));
+        self.image = load_texture(&self.path, color_space);
+    }
+
+    fn init_data(&mut self) {
+        self.image = load_texture(&self.path, self.color_space.unwrap_or_default());
+    }
+
+    fn color(&self, surface: &SurfacePoint) -> [f32; 3] {
+        let (u, v) = surface.uv.unwrap_or((0.0, 0.0));
+        let footprint = surface.footprint * self.scale[0].abs().max(self.scale[1].abs());
+        self.image.sample(u * self.scale[0], v * self.scale[1], footprint, &self.sampler)
+    }
+}
@@ -0,0 +1,95 @@
+use image::{Rgb32FImage,RgbImage};
+use serde::{Serialize,Deserialize};
+use rand::{Rng,SeedableRng};
+use rand::rngs::SmallRng;
+
+#[derive(Serialize,Deserialize,Default,Clone,Copy,PartialEq,Debug)]
+#[serde(rename_all="lowercase")]
+pub enum Operator {
+    /// Values above 1 are clipped
+    #[default]
+    None,
+    /// c / (1 + c), extended so that `white` maps to 1
+    Reinhard,
+    /// Narkowicz fit of the ACES reference rendering transform
+    Aces,
+    /// Hable's Uncharted 2 curve, normalized by `white`
+    Filmic,
+}
+
+/// Conversion of linear HDR colors to 8-bit output
+#[derive(Serialize,Deserialize,Debug)]
+#[serde(default)]
+pub struct ToneMap {
+    /// Exposure adjustment in stops
+    pub exposure: f32,
+    pub operator: Operator,
+    /// Smallest linear value mapped to full white by `reinhard` and `filmic`
+    pub white: f32,
+    /// Encode with the sRGB transfer curve instead of writing linear values
+    pub srgb: bool,
+    /// Add triangular noise of one quantization step to hide banding
+    pub dither: bool,
+}
+
+impl Default for ToneMap {
+    /// Without a `tonemap` block colors are clipped and written linearly, as they always were
+    fn default() -> ToneMap {
+        ToneMap {
+            exposure: 0.0,
+            operator: Operator::None,
+            white: 11.2,
+            srgb: false,
+            dither: false,
+        }
+    }
+}
+
+impl ToneMap {
+    fn filmic_curve(x: f32) -> f32 {
+        let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+        ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+    }
+
+    /// Maps a linear value to [0, 1] display space, before encoding
+    pub fn map(&self, c: f32) -> f32 {
+        let c = f32::max(c, 0.0) * self.exposure.exp2();
+        let mapped = match self.operator {
+            Operator::None => c,
+            Operator::Reinhard => c * (1.0 + c / (self.white * self.white)) / (1.0 + c),
+            Operator::Aces => (c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14),
+            Operator::Filmic => ToneMap::filmic_curve(2.0 * c) / ToneMap::filmic_curve(self.white),
+        };
+        mapped.clamp(0.0, 1.0)
+    }
+
+    pub fn srgb_encode(c: f32) -> f32 {
+        if c <= 0.0031308 {
+            12.92 * c
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        }
+    }
+
+    pub fn apply(&self, framebuffer: &Rgb32FImage) -> RgbImage {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut img = RgbImage::new(framebuffer.width(), framebuffer.height());
+        for (x, y, pixel) in framebuffer.enumerate_pixels() {
+            let color = pixel.0.map(|c| {
+                let mut c = self.map(c);
+                if self.srgb {
+                    c = ToneMap::srgb_encode(c);
+                }
+                let value = if self.dither {
+                    (255.0 * c + rng.gen::<f32>() - rng.gen::<f32>()).round()
+                } else {
+                    // Truncated like the plain 8-bit conversion, so old scenes render the same
+                    (255.0 * c).floor()
+                };
+                value.clamp(0.0, 255.0) as u8
+            });
+            img.put_pixel(x, y, image::Rgb(color));
+        }
+        img
+    }
+}
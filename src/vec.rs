@@ -1,4 +1,4 @@
-use std::ops::{Add,AddAssign,Sub,SubAssign,Mul,MulAssign,Div,DivAssign,Neg};
+use std::ops::{Add,AddAssign,Sub,SubAssign,Mul,MulAssign,Div,DivAssign,Neg,Index};
 use std::cmp::PartialEq;
 use serde::{Serialize,Deserialize};
 
@@ -18,9 +18,18 @@ impl PartialEq for Vec3 {
             */
         (self - rhs).length() < 1e-5
     }
+}
+
+impl Index<usize> for Vec3 {
+    type Output = f32;
 
-    fn ne(&self, rhs: &Vec3) -> bool {
-        !(self == rhs)
+    fn index(&self, i: usize) -> &f32 {
+        match i {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 index out of range: {}", i),
+        }
     }
 }
 
@@ -330,6 +339,14 @@ impl Vec3 {
         self / self.length()
     }
 
+    pub fn min(&self, rhs: &Vec3) -> Vec3 {
+        Vec3::new(self.x.min(rhs.x), self.y.min(rhs.y), self.z.min(rhs.z))
+    }
+
+    pub fn max(&self, rhs: &Vec3) -> Vec3 {
+        Vec3::new(self.x.max(rhs.x), self.y.max(rhs.y), self.z.max(rhs.z))
+    }
+
     pub fn x(&self) -> f32 {
         self.x
     }